@@ -0,0 +1,308 @@
+// A small HTTP endpoint for operators to see and poke at a running server:
+//
+//   GET  /connections             every open connection
+//   POST /connections/<id>/kill   hang up on one of them
+//   POST /log-level/<level>       log at `level` (or `default` for RUST_LOG's)
+//   POST /drain                   stop taking connections, and exit once the
+//                                 open ones have finished
+//   GET  /metrics                 every counter in `metrics`
+//
+// It only listens on localhost, since anyone who can reach it can do all that.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn, LevelFilter, Log, Metadata, Record};
+
+use crate::{metrics, Service};
+
+// Operators are people with curl, so they needn't wait long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Longest request line or header we'll read.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+// Everything we keep about an open connection.
+pub(crate) struct ConnectionInfo {
+    id: u64,
+    client: SocketAddr,
+    started: Instant,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    // What the service says it's up to.
+    state: Mutex<String>,
+    // A handle on the handler's stream, to hang up with.
+    stream: TcpStream,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn record_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_state(&self, state: String) {
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+// The open connections to a server, and whether it's draining.
+pub struct Registry {
+    service: Option<Service>,
+    next_id: AtomicU64,
+    connections: Mutex<BTreeMap<u64, Arc<ConnectionInfo>>>,
+    draining: AtomicBool,
+    // Where to poke the server to notice it's draining.
+    listen_addr: SocketAddr,
+}
+
+impl Registry {
+    pub(crate) fn new(service: Option<Service>, listen_addr: SocketAddr) -> Registry {
+        Registry {
+            service,
+            next_id: AtomicU64::new(1),
+            connections: Mutex::new(BTreeMap::new()),
+            draining: AtomicBool::new(false),
+            listen_addr,
+        }
+    }
+
+    pub(crate) fn register(
+        &self,
+        stream: &TcpStream,
+        client: SocketAddr,
+    ) -> io::Result<Arc<ConnectionInfo>> {
+        let info = Arc::new(ConnectionInfo {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            client,
+            started: Instant::now(),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            state: Mutex::new(String::new()),
+            stream: stream.try_clone()?,
+        });
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(info.id, info.clone());
+        Ok(info)
+    }
+
+    pub(crate) fn deregister(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    fn drain(&self) -> usize {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            info!("Draining.");
+            // The accept loop only checks after a connection arrives, so give
+            // it one.
+            let _ = TcpStream::connect(("127.0.0.1", self.listen_addr.port()));
+        }
+        self.connections.lock().unwrap().len()
+    }
+
+    fn kill(&self, id: u64) -> bool {
+        match self.connections.lock().unwrap().get(&id) {
+            Some(info) => {
+                info!("Killing connection {} from {}.", id, info.client);
+                let _ = info.stream.shutdown(Shutdown::Both);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let connections = self.connections.lock().unwrap();
+        let mut description = String::new();
+        let service = self
+            .service
+            .and_then(|s| clap::ValueEnum::to_possible_value(&s))
+            .map_or("unknown", |v| v.get_name());
+        let _ = writeln!(
+            description,
+            "service={} connections={} draining={}",
+            service,
+            connections.len(),
+            self.is_draining()
+        );
+        for info in connections.values() {
+            let _ = writeln!(
+                description,
+                "id={} client={} age={:.1}s received={} sent={} state={}",
+                info.id,
+                info.client,
+                info.started.elapsed().as_secs_f64(),
+                info.bytes_received.load(Ordering::Relaxed),
+                info.bytes_sent.load(Ordering::Relaxed),
+                info.state.lock().unwrap()
+            );
+        }
+        description
+    }
+}
+
+// Serves admin requests about `registry` on localhost's `port`, on a thread
+// of its own.
+pub fn serve(port: usize, registry: Arc<Registry>) -> io::Result<()> {
+    let listener = TcpListener::bind(format!("127.0.0.1:{}", port))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle_request(stream, &registry) {
+                warn!("Admin request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle_request(stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let request_line = read_line(&mut reader)?;
+    // We've no use for the headers, but they need reading before we answer.
+    while !read_line(&mut reader)?.is_empty() {}
+
+    let mut words = request_line.split(' ');
+    let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    let (status, body) = route(method, path, registry);
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE_LENGTH).read_line(&mut line)?;
+    Ok(line.trim_end().to_string())
+}
+
+fn route(method: &str, path: &str, registry: &Registry) -> (&'static str, String) {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["connections"]) => ("200 OK", registry.describe()),
+        ("POST", ["connections", id, "kill"]) => match id.parse() {
+            Ok(id) if registry.kill(id) => ("200 OK", format!("Killed connection {}.\n", id)),
+            _ => ("404 Not Found", format!("No connection {}.\n", id)),
+        },
+        ("POST", ["log-level", level]) => match set_log_level(level) {
+            Some(level) => ("200 OK", format!("Log level is {}.\n", level)),
+            None => (
+                "400 Bad Request",
+                format!(
+                    "Unknown log level {}. Try off, error, warn, info, debug, trace or default.\n",
+                    level
+                ),
+            ),
+        },
+        ("POST", ["drain"]) => (
+            "202 Accepted",
+            format!("Draining {} connections.\n", registry.drain()),
+        ),
+        ("GET", ["metrics"]) => {
+            let mut body = String::new();
+            for (name, value) in metrics::snapshot() {
+                let _ = writeln!(body, "{} {}", name, value);
+            }
+            ("200 OK", body)
+        }
+        _ => (
+            "404 Not Found",
+            format!("Nothing at {} {}.\n", method, path),
+        ),
+    }
+}
+
+// Whether the log level's been changed from what RUST_LOG says.
+static LOG_LEVEL_OVERRIDDEN: AtomicBool = AtomicBool::new(false);
+static DEFAULT_LOG_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+
+// Logs as RUST_LOG says, until told otherwise through the admin endpoint.
+struct Logger {
+    from_env: env_logger::Logger,
+    everything: env_logger::Logger,
+}
+
+impl Logger {
+    fn current(&self) -> &env_logger::Logger {
+        // With an overridden level, the `log` macros do the filtering.
+        if LOG_LEVEL_OVERRIDDEN.load(Ordering::Relaxed) {
+            &self.everything
+        } else {
+            &self.from_env
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.current().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.current().log(record)
+    }
+
+    fn flush(&self) {
+        self.current().flush()
+    }
+}
+
+// Use instead of `env_logger::init` for a log level that can be changed at
+// runtime.
+pub fn init_logging() {
+    let from_env = env_logger::Builder::from_default_env().build();
+    let everything = env_logger::Builder::new()
+        .filter_level(LevelFilter::Trace)
+        .build();
+    let default = from_env.filter();
+    DEFAULT_LOG_LEVEL.get_or_init(|| default);
+
+    log::set_boxed_logger(Box::new(Logger {
+        from_env,
+        everything,
+    }))
+    .unwrap();
+    log::set_max_level(default);
+}
+
+// Sets the log level by name, returning it if the name's one we know.
+fn set_log_level(name: &str) -> Option<LevelFilter> {
+    if name == "default" {
+        let level = *DEFAULT_LOG_LEVEL.get().unwrap_or(&LevelFilter::Error);
+        LOG_LEVEL_OVERRIDDEN.store(false, Ordering::Relaxed);
+        log::set_max_level(level);
+        return Some(level);
+    }
+    let level = name.parse().ok()?;
+    LOG_LEVEL_OVERRIDDEN.store(true, Ordering::Relaxed);
+    log::set_max_level(level);
+    Some(level)
+}
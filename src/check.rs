@@ -0,0 +1,150 @@
+// Conformance checks in the spirit of the Protohackers grader, for running
+// against our own servers (or anyone's) without submitting them.
+
+use std::fmt;
+use std::io;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::Service;
+
+mod means_to_an_end;
+mod prime_time;
+mod smoke_test;
+
+// A server that takes longer than this to answer fails the scenario.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The grader wants every service to handle at least this many clients at once.
+const SIMULTANEOUS_CLIENTS: usize = 5;
+
+#[derive(Debug, Error)]
+pub enum CheckError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Failed(String),
+}
+
+type CheckResult = Result<(), CheckError>;
+
+// One thing we check a server does right.
+pub struct Scenario {
+    pub name: &'static str,
+    // Runs the scenario against the server at the given address.
+    run: fn(&str) -> CheckResult,
+}
+
+pub fn scenarios(service: Service) -> Vec<Scenario> {
+    match service {
+        Service::SmokeTest => smoke_test::scenarios(),
+        Service::PrimeTime => prime_time::scenarios(),
+        Service::MeansToAnEnd => means_to_an_end::scenarios(),
+    }
+}
+
+pub struct CheckReport {
+    pub results: Vec<(&'static str, CheckResult)>,
+}
+
+impl CheckReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "PASS {}", name)?,
+                Err(e) => writeln!(f, "FAIL {}: {}", name, e)?,
+            }
+        }
+        let passed = self.results.iter().filter(|(_, r)| r.is_ok()).count();
+        write!(f, "{}/{} scenarios passed.", passed, self.results.len())
+    }
+}
+
+// Runs every scenario for `service` against the server at `address`, one
+// after the other.
+pub fn run(service: Service, address: &str) -> CheckReport {
+    let results = scenarios(service)
+        .into_iter()
+        .map(|scenario| (scenario.name, (scenario.run)(address)))
+        .collect();
+    CheckReport { results }
+}
+
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESPONSE_TIMEOUT))?;
+    Ok(stream)
+}
+
+fn check_eq<T: PartialEq + fmt::Debug>(what: &str, expected: T, got: T) -> CheckResult {
+    if expected == got {
+        Ok(())
+    } else {
+        Err(CheckError::Failed(format!(
+            "expected {} to be {:?}, got {:?}",
+            what, expected, got
+        )))
+    }
+}
+
+// Runs `client` for `SIMULTANEOUS_CLIENTS` clients at once, each given its
+// index, and fails if any of them do.
+fn simultaneously(address: &str, client: fn(&str, usize) -> CheckResult) -> CheckResult {
+    thread::scope(|scope| {
+        let clients: Vec<_> = (0..SIMULTANEOUS_CLIENTS)
+            .map(|i| scope.spawn(move || client(address, i)))
+            .collect();
+        clients
+            .into_iter()
+            .map(|c| c.join().unwrap())
+            .collect::<Result<Vec<()>, _>>()
+    })?;
+    Ok(())
+}
+
+// Whether the server has closed the connection, waiting a moment for it to.
+fn is_closed(stream: &TcpStream) -> bool {
+    let mut buf = [0; 1];
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+    match stream.peek(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => !matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_eq, CheckError, CheckReport};
+
+    #[test]
+    fn test_report() {
+        let report = CheckReport {
+            results: vec![("good", Ok(())), ("bad", check_eq("the answer", 42, 41))],
+        };
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "PASS good\nFAIL bad: expected the answer to be 42, got 41\n1/2 scenarios passed."
+        );
+
+        let report = CheckReport {
+            results: vec![("good", Ok::<(), CheckError>(()))],
+        };
+        assert!(report.passed());
+    }
+}
@@ -0,0 +1,135 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use super::{check_eq, connect, simultaneously, CheckError, CheckResult, Scenario};
+use crate::means_to_an_end::Message;
+
+pub fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: "answers the example session",
+            run: |address| {
+                let mut stream = connect(address)?;
+                for (timestamp, price) in [(12345, 101), (12346, 102), (12347, 100), (40960, 5)] {
+                    insert(&mut stream, timestamp, price)?;
+                }
+                check_eq("mean", 101, query(&mut stream, 12288, 16384)?)
+            },
+        },
+        Scenario {
+            name: "answers 0 for empty and backwards ranges",
+            run: |address| {
+                let mut stream = connect(address)?;
+                check_eq("mean of nothing", 0, query(&mut stream, 0, 1000)?)?;
+                insert(&mut stream, 10, 100)?;
+                check_eq("mean of a backwards range", 0, query(&mut stream, 20, 0)?)
+            },
+        },
+        Scenario {
+            name: "handles negative prices and timestamps",
+            run: |address| {
+                let mut stream = connect(address)?;
+                insert(&mut stream, -100, -50)?;
+                insert(&mut stream, -99, -150)?;
+                check_eq("mean", -100, query(&mut stream, i32::MIN, i32::MAX)?)
+            },
+        },
+        Scenario {
+            name: "keeps sessions separate",
+            run: |address| {
+                let mut first = connect(address)?;
+                let mut second = connect(address)?;
+                insert(&mut first, 1, 100)?;
+                check_eq("first session's mean", 100, query(&mut first, 0, 10)?)?;
+                check_eq("second session's mean", 0, query(&mut second, 0, 10)?)
+            },
+        },
+        Scenario {
+            name: "handles messages split across writes",
+            run: |address| {
+                let mut stream = connect(address)?;
+                stream.set_nodelay(true)?;
+                let mut bytes = Message::Insert {
+                    timestamp: 1,
+                    price: 42,
+                }
+                .to_frame();
+                bytes.extend(
+                    Message::Query {
+                        mintime: 0,
+                        maxtime: 2,
+                    }
+                    .to_frame(),
+                );
+                for byte in bytes {
+                    stream.write_all(&[byte])?;
+                    thread::sleep(Duration::from_millis(5));
+                }
+                check_eq("mean", 42, read_mean(&mut stream)?)
+            },
+        },
+        Scenario {
+            name: "handles many inserts in one write",
+            run: |address| {
+                let mut stream = connect(address)?;
+                let mut bytes = vec![];
+                for timestamp in 0..20_000 {
+                    let price = timestamp % 1000;
+                    bytes.extend(Message::Insert { timestamp, price }.to_frame());
+                }
+                stream.write_all(&bytes)?;
+                // Prices 0 to 999, twenty times over.
+                check_eq("mean", 499, query(&mut stream, 0, 20_000)?)
+            },
+        },
+        Scenario {
+            name: "survives malformed messages",
+            run: |address| {
+                // What the server does with these is up to it, so long as it
+                // carries on serving everyone else.
+                let mut stream = connect(address)?;
+                let _ = stream.write_all(b"X\x00\x00\x00\x01\x00\x00\x00\x02garbage");
+                drop(stream);
+
+                let mut stream = connect(address)?;
+                insert(&mut stream, 1, 7)?;
+                check_eq(
+                    "mean after malformed messages",
+                    7,
+                    query(&mut stream, 1, 1)?,
+                )
+            },
+        },
+        Scenario {
+            name: "handles simultaneous clients",
+            run: |address| {
+                simultaneously(address, |address, i| {
+                    let mut stream = connect(address)?;
+                    let price = i as i32 * 100;
+                    for timestamp in 0..100 {
+                        insert(&mut stream, timestamp, price)?;
+                    }
+                    check_eq("mean", price, query(&mut stream, 0, 100)?)
+                })
+            },
+        },
+    ]
+}
+
+fn insert(stream: &mut TcpStream, timestamp: i32, price: i32) -> CheckResult {
+    stream.write_all(&Message::Insert { timestamp, price }.to_frame())?;
+    Ok(())
+}
+
+fn query(stream: &mut TcpStream, mintime: i32, maxtime: i32) -> Result<i32, CheckError> {
+    stream.write_all(&Message::Query { mintime, maxtime }.to_frame())?;
+    read_mean(stream)
+}
+
+fn read_mean(stream: &mut TcpStream) -> Result<i32, CheckError> {
+    let mut mean = [0; 4];
+    stream.read_exact(&mut mean)?;
+    Ok(i32::from_be_bytes(mean))
+}
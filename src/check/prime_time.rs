@@ -0,0 +1,172 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+
+use super::{check_eq, connect, is_closed, simultaneously, CheckError, CheckResult, Scenario};
+
+// Requests the spec says are fine, and whether the number in each is prime.
+const CONFORMING_REQUESTS: &[(&str, bool)] = &[
+    (r#"{"method":"isPrime","number":2}"#, true),
+    (r#"{"method":"isPrime","number":7919}"#, true),
+    (r#"{"method":"isPrime","number":7917}"#, false),
+    (r#"{"method":"isPrime","number":0}"#, false),
+    (r#"{"method":"isPrime","number":1}"#, false),
+    (r#"{"method":"isPrime","number":-3}"#, false),
+    (r#"{"method":"isPrime","number":7.5}"#, false),
+    (r#"{"method":"isPrime","number":2147483647}"#, true),
+    (
+        r#"{"method":"isPrime","number":18446744073709551557}"#,
+        true,
+    ),
+    (
+        r#"{"method":"isPrime","number":170141183460469231731687303715884105727}"#,
+        true,
+    ),
+    // Extra fields are ignored.
+    (r#"{"method":"isPrime","number":13,"extra":"field"}"#, true),
+    (r#"{ "number" : 12 , "method" : "isPrime" }"#, false),
+];
+
+// Requests the spec says get a malformed response and a disconnect.
+const MALFORMED_REQUESTS: &[&str] = &[
+    "{",
+    "not json",
+    "{}",
+    r#"{"number":7}"#,
+    r#"{"method":"isPrime"}"#,
+    r#"{"method":"isNotPrime","number":7}"#,
+    r#"{"method":"isPrime","number":"7"}"#,
+    r#"{"method":"isPrime","number":null}"#,
+    r#"["isPrime",7]"#,
+];
+
+pub fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: "answers conforming requests",
+            run: |address| {
+                let mut connection = Connection::new(address)?;
+                for (request, prime) in CONFORMING_REQUESTS {
+                    let response = connection.request(request)?;
+                    check_response(request, &response, *prime)?;
+                }
+                Ok(())
+            },
+        },
+        Scenario {
+            name: "rejects malformed requests and disconnects",
+            run: |address| {
+                for request in MALFORMED_REQUESTS {
+                    let mut connection = Connection::new(address)?;
+                    let response = connection.request(request)?;
+                    if json::parse(&response)
+                        .map(|r| is_conforming_response(&r))
+                        .unwrap_or(false)
+                    {
+                        return Err(CheckError::Failed(format!(
+                            "{} got a conforming response: {}",
+                            request, response
+                        )));
+                    }
+                    if !is_closed(&connection.stream) {
+                        return Err(CheckError::Failed(format!(
+                            "connection still open after {}",
+                            request
+                        )));
+                    }
+                }
+                Ok(())
+            },
+        },
+        Scenario {
+            name: "answers pipelined requests in order",
+            run: |address| check_many_requests(address, 0, 1000),
+        },
+        Scenario {
+            name: "handles a large batch of requests",
+            run: |address| check_many_requests(address, 1_000_000, 20_000),
+        },
+        Scenario {
+            name: "handles simultaneous clients",
+            run: |address| {
+                simultaneously(address, |address, i| {
+                    check_many_requests(address, i as u64 * 1000, 200)
+                })
+            },
+        },
+    ]
+}
+
+struct Connection {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    fn new(address: &str) -> Result<Connection, CheckError> {
+        let stream = connect(address)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok(Connection { stream, reader })
+    }
+
+    fn request(&mut self, request: &str) -> Result<String, CheckError> {
+        self.stream.write_all(format!("{}\n", request).as_bytes())?;
+        self.read_response()
+    }
+
+    // Reads a response line. A malformed response might not end in a newline,
+    // in which case it's everything up to the disconnect.
+    fn read_response(&mut self) -> Result<String, CheckError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(CheckError::Failed("no response".to_string()));
+        }
+        Ok(line.trim_end().to_string())
+    }
+}
+
+fn is_conforming_response(response: &json::JsonValue) -> bool {
+    response.is_object() && response["method"] == "isPrime" && response["prime"].is_boolean()
+}
+
+fn check_response(request: &str, response: &str, prime: bool) -> CheckResult {
+    match json::parse(response) {
+        Ok(r) if is_conforming_response(&r) => check_eq(
+            &format!("the answer to {}", request),
+            prime,
+            r["prime"] == true,
+        ),
+        _ => Err(CheckError::Failed(format!(
+            "{} got a malformed response: {}",
+            request, response
+        ))),
+    }
+}
+
+// Sends isPrime requests for `count` numbers from `start` in one go, then
+// checks the answers.
+fn check_many_requests(address: &str, start: u64, count: u64) -> CheckResult {
+    let mut connection = Connection::new(address)?;
+    let numbers = start..start + count;
+
+    let requests: String = numbers
+        .clone()
+        .map(|n| format!("{{\"method\":\"isPrime\",\"number\":{}}}\n", n))
+        .collect();
+    // Writing all of these at once could fill both sides' buffers if we aren't
+    // reading too.
+    let mut stream = connection.stream.try_clone()?;
+    let writer = std::thread::spawn(move || stream.write_all(requests.as_bytes()));
+
+    let result = numbers.into_iter().try_for_each(|n| {
+        let response = connection.read_response()?;
+        check_response(&format!("number {}", n), &response, primal::is_prime(n))
+    });
+    if result.is_err() {
+        // Stop the writer if it's still going.
+        let _ = connection.stream.shutdown(Shutdown::Both);
+    }
+    let written = writer.join().unwrap();
+    result?;
+    written?;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+
+use super::{check_eq, connect, simultaneously, CheckResult, Scenario};
+
+pub fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: "echoes a message",
+            run: |address| check_echo(address, b"Hello, world!"),
+        },
+        Scenario {
+            name: "echoes nothing",
+            run: |address| check_echo(address, b""),
+        },
+        Scenario {
+            name: "echoes every byte value",
+            run: |address| check_echo(address, &(0..=255).collect::<Vec<u8>>()),
+        },
+        Scenario {
+            name: "echoes a large payload",
+            run: |address| check_echo(address, &payload(4 * 1024 * 1024, 0)),
+        },
+        Scenario {
+            name: "handles simultaneous clients",
+            run: |address| {
+                simultaneously(address, |address, i| {
+                    check_echo(address, &payload(100_000, i as u32))
+                })
+            },
+        },
+    ]
+}
+
+// Bytes that vary enough to catch an echo getting mixed up.
+fn payload(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_add(seed).wrapping_mul(2654435761) >> 24) as u8)
+        .collect()
+}
+
+// Sends `payload`, half-closes, and checks exactly `payload` comes back.
+fn check_echo(address: &str, payload: &[u8]) -> CheckResult {
+    let stream = connect(address)?;
+
+    // Write while reading, since the server may not read more until we've
+    // taken what it's echoed.
+    let echo = thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            let mut stream: &TcpStream = &stream;
+            stream.write_all(payload)?;
+            stream.shutdown(Shutdown::Write)
+        });
+
+        let mut echo = vec![];
+        let read = (&stream).read_to_end(&mut echo);
+        writer.join().unwrap()?;
+        read.map(|_| echo)
+    })?;
+
+    check_eq("echo length", payload.len(), echo.len())?;
+    if let Some(i) = echo.iter().zip(payload).position(|(a, b)| a != b) {
+        return check_eq(&format!("byte {}", i), payload[i], echo[i]);
+    }
+    Ok(())
+}
@@ -1,22 +1,364 @@
-use std::net::{TcpListener, TcpStream};
+use log::{info, warn};
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use threadpool::ThreadPool;
 
+pub mod admin;
+pub mod check;
+pub mod limits;
+pub mod line_reader;
+pub mod load;
 pub mod means_to_an_end;
+pub mod metrics;
 pub mod prime_time;
+pub mod proxy_protocol;
 pub mod smoke_test;
+pub mod tls;
 
-pub fn run_server<F>(port: Option<usize>, num_workers: usize, connection_handler: F)
+// The services we implement, for commands that work with any of them.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum Service {
+    SmokeTest,
+    PrimeTime,
+    MeansToAnEnd,
+}
+
+// Where clients look for a server that was started without a port.
+pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:5001";
+
+// How `run_server` listens, whatever the service.
+pub struct ServerOptions {
+    // Which service this is, for the admin endpoint.
+    pub service: Option<Service>,
+    pub port: Option<usize>,
+    pub num_workers: usize,
+    // Expect every connection to start with a PROXY protocol header, and
+    // treat the client it names as the one we're talking to.
+    pub proxy_protocol: bool,
+    // Terminate TLS on every connection before the handler sees it.
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    pub limits: limits::Config,
+    // Serve the admin endpoint on this port on localhost.
+    pub admin_port: Option<usize>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            service: None,
+            port: None,
+            num_workers: 5,
+            proxy_protocol: false,
+            tls: None,
+            limits: limits::Config::default(),
+            admin_port: None,
+        }
+    }
+}
+
+// What a handler knows about the connection it's been given, beyond the
+// stream itself.
+pub struct Connection {
+    // Who's on the other end, even if they came through a proxy.
+    pub client: SocketAddr,
+    permit: Option<limits::Permit>,
+    info: Arc<admin::ConnectionInfo>,
+    registry: Arc<admin::Registry>,
+}
+
+impl Connection {
+    // Unique among this server's connections.
+    pub fn id(&self) -> u64 {
+        self.info.id()
+    }
+
+    // Handlers report what they read and write, for the admin endpoint.
+    pub fn record_received(&self, bytes: usize) {
+        self.info.record_received(bytes);
+    }
+
+    pub fn record_sent(&self, bytes: usize) {
+        self.info.record_sent(bytes);
+    }
+
+    // A few words on what the handler's up to, for the admin endpoint.
+    pub fn set_state(&self, state: impl Into<String>) {
+        self.info.set_state(state.into());
+    }
+
+    // Handlers call this before acting on each message, to hold the client to
+    // any message rate limit.
+    pub fn wait_for_message(&self) {
+        if let Some(permit) = &self.permit {
+            permit.wait_for_message();
+        }
+    }
+
+    // The connection counts towards its address's limits until the handler
+    // returns. Handlers that tell the client they're done any sooner call
+    // this first, so it can connect again as soon as it hears.
+    pub fn release_limits(&mut self) {
+        self.permit = None;
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.registry.deregister(self.info.id());
+    }
+}
+
+// Connections we'll be setting up at once on threads of their own, waiting on
+// the client for a PROXY header or TLS handshake. Any more are dropped until
+// one finishes.
+const MAX_HANDSHAKES: usize = 64;
+
+// Runs `connection_handler` on every connection to `options.port`, until
+// drained through the admin endpoint.
+pub fn run_server<F>(options: ServerOptions, connection_handler: F)
 where
-    F: Fn(TcpStream) + Send + Copy + 'static,
+    F: Fn(TcpStream, Connection) + Send + Sync + 'static,
 {
-    let bind_addr = match port {
+    let bind_addr = match options.port {
         Some(p) => format!("0.0.0.0:{}", p),
         None => "0.0.0.0:5001".to_string(),
     };
 
     let listener = TcpListener::bind(bind_addr).unwrap();
-    let pool = ThreadPool::new(num_workers);
+    let registry = Arc::new(admin::Registry::new(
+        options.service,
+        listener.local_addr().unwrap(),
+    ));
+    if let Some(port) = options.admin_port {
+        admin::serve(port, registry.clone()).unwrap();
+    }
+
+    let dispatcher = Arc::new(Dispatcher {
+        pool: ThreadPool::new(options.num_workers),
+        handshakes: (options.proxy_protocol || options.tls.is_some())
+            .then(|| ThreadPool::new(MAX_HANDSHAKES)),
+        handshaking: AtomicUsize::new(0),
+        proxy_protocol: options.proxy_protocol,
+        tls: options.tls.clone(),
+        limiter: limits::Limiter::new(options.limits.clone()),
+        registry: registry.clone(),
+        relays: Relays::default(),
+        handler: connection_handler,
+    });
     for stream in listener.incoming() {
-        pool.execute(move || connection_handler(stream.unwrap()));
+        if registry.is_draining() {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Couldn't accept a connection: {}", e);
+                continue;
+            }
+        };
+        let Ok(peer) = stream.peer_addr() else {
+            continue;
+        };
+        dispatcher.dispatch(stream, peer);
+    }
+
+    // Turn away anyone new while the connections we have finish up.
+    drop(listener);
+    if let Some(handshakes) = &dispatcher.handshakes {
+        handshakes.join();
+    }
+    dispatcher.pool.join();
+    dispatcher.relays.join();
+    info!("Drained.");
+}
+
+// Gets accepted connections past whatever comes before the service's own
+// protocol, and onto a worker.
+struct Dispatcher<F> {
+    pool: ThreadPool,
+    // For PROXY headers and TLS handshakes, which wait on the client, so that
+    // slow or silent clients can't tie up the workers. Only there if there's
+    // any to do.
+    handshakes: Option<ThreadPool>,
+    // How many connections are on `handshakes`, whether or not a thread has
+    // got to them yet.
+    handshaking: AtomicUsize,
+    proxy_protocol: bool,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    limiter: limits::Limiter,
+    registry: Arc<admin::Registry>,
+    relays: Relays,
+    handler: F,
+}
+
+impl<F> Dispatcher<F>
+where
+    F: Fn(TcpStream, Connection) + Send + Sync + 'static,
+{
+    // Sees a connection from `peer` onto a worker, if it's within the limits
+    // and everything before the service's protocol goes to plan.
+    fn dispatch(self: &Arc<Self>, stream: TcpStream, peer: SocketAddr) {
+        if self.proxy_protocol {
+            // The limits are for the client behind the proxy, so they have to
+            // wait for the header too.
+            self.handshake(
+                peer,
+                move |dispatcher| match proxy_protocol::read_client_address(&stream) {
+                    Ok(client) => {
+                        if let Some((stream, permit)) = dispatcher.admit(stream, client) {
+                            dispatcher.set_up(stream, client, permit);
+                        }
+                    }
+                    Err(e) => warn!("Dropping connection from {}: {}", peer, e),
+                },
+            );
+        } else if let Some((stream, permit)) = self.admit(stream, peer) {
+            if self.tls.is_some() {
+                self.handshake(peer, move |dispatcher| {
+                    dispatcher.set_up(stream, peer, permit)
+                });
+            } else {
+                self.hand_to_worker(stream, peer, permit);
+            }
+        }
+    }
+
+    // Holds the connection from `client` to the limits. Returns the stream to
+    // use from now on, or None if it's turned away.
+    fn admit(
+        &self,
+        stream: TcpStream,
+        client: SocketAddr,
+    ) -> Option<(TcpStream, Option<limits::Permit>)> {
+        let permit = match self.limiter.admit(client.ip()) {
+            Ok(permit) => permit,
+            Err(rejection) => {
+                warn!("Rejecting connection from {}: {}", client, rejection);
+                metrics::increment("limits.connections_rejected");
+                return None;
+            }
+        };
+        let stream = match &permit {
+            Some(permit) if self.limiter.throttles_bytes() => {
+                match permit.throttle(stream, &self.relays) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Couldn't throttle connection from {}: {}", client, e);
+                        return None;
+                    }
+                }
+            }
+            _ => stream,
+        };
+        Some((stream, permit))
+    }
+
+    // Does any TLS handshake, then hands the connection to a worker.
+    fn set_up(
+        self: &Arc<Self>,
+        stream: TcpStream,
+        client: SocketAddr,
+        permit: Option<limits::Permit>,
+    ) {
+        let stream = match self.tls.clone() {
+            Some(config) => match tls::terminate(stream, config, &self.relays) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("TLS handshake with {} failed: {}", client, e);
+                    return;
+                }
+            },
+            None => stream,
+        };
+        self.hand_to_worker(stream, client, permit);
+    }
+
+    // Runs `f` on a handshake thread, unless they're all taken, in which case
+    // the connection from `client` is dropped.
+    fn handshake(
+        self: &Arc<Self>,
+        client: SocketAddr,
+        f: impl FnOnce(&Arc<Self>) + Send + 'static,
+    ) {
+        let Some(handshakes) = &self.handshakes else {
+            return f(self);
+        };
+        if self.handshaking.fetch_add(1, Ordering::SeqCst) >= MAX_HANDSHAKES {
+            self.handshaking.fetch_sub(1, Ordering::SeqCst);
+            warn!("Dropping connection from {}: too many handshakes", client);
+            metrics::increment("limits.handshakes_rejected");
+            return;
+        }
+        let dispatcher = self.clone();
+        handshakes.execute(move || {
+            f(&dispatcher);
+            dispatcher.handshaking.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    fn hand_to_worker(
+        self: &Arc<Self>,
+        stream: TcpStream,
+        client: SocketAddr,
+        permit: Option<limits::Permit>,
+    ) {
+        let dispatcher = self.clone();
+        self.pool.execute(move || {
+            let Ok(info) = dispatcher.registry.register(&stream, client) else {
+                return;
+            };
+            let connection = Connection {
+                client,
+                permit,
+                info,
+                registry: dispatcher.registry.clone(),
+            };
+            (dispatcher.handler)(stream, connection);
+        });
+    }
+}
+
+// Threads passing what a handler wrote on to its client, which can still be
+// at it after the handler's returned. Draining waits for them, so that
+// clients get everything.
+#[derive(Default)]
+pub(crate) struct Relays {
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Relays {
+    pub(crate) fn spawn(&self, relay: impl FnOnce() + Send + 'static) {
+        let mut handles = self.handles.lock().unwrap();
+        handles.retain(|handle| !handle.is_finished());
+        handles.push(thread::spawn(relay));
+    }
+
+    // Waits for every relay spawned so far to finish.
+    fn join(&self) {
+        let handles = mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+// A connected pair of loopback streams, for relaying a connection to a
+// handler after doing something to it on the way.
+pub(crate) fn loopback_pair() -> io::Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let ours = TcpStream::connect(listener.local_addr()?)?;
+    loop {
+        let (theirs, peer) = listener.accept()?;
+        // Anyone could connect to the listener while it's up, so make sure
+        // it's us.
+        if peer == ours.local_addr()? {
+            ours.set_nodelay(true)?;
+            theirs.set_nodelay(true)?;
+            return Ok((ours, theirs));
+        }
     }
 }
@@ -0,0 +1,369 @@
+// Per-source-IP limits, so one client can't hog every worker or all our
+// bandwidth. Connections over a limit are turned away; bytes and messages
+// over a rate are slowed down rather than refused.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+// Once we're tracking this many addresses, forget the ones we've no reason to
+// remember any more.
+const PRUNE_THRESHOLD: usize = 1024;
+
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+// Rates allow bursts of up to a second's worth.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub max_connections_per_ip: Option<usize>,
+    pub connections_per_second: Option<f64>,
+    pub bytes_per_second: Option<f64>,
+    pub messages_per_second: Option<f64>,
+    // Addresses none of the limits apply to.
+    pub allowlist: Vec<IpAddr>,
+}
+
+impl Config {
+    fn is_unlimited(&self) -> bool {
+        self.max_connections_per_ip.is_none()
+            && self.connections_per_second.is_none()
+            && self.bytes_per_second.is_none()
+            && self.messages_per_second.is_none()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    TooManyConnections(usize),
+    TooManyNewConnections,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::TooManyConnections(limit) => {
+                write!(f, "already has {} connections open", limit)
+            }
+            Rejection::TooManyNewConnections => write!(f, "connecting too often"),
+        }
+    }
+}
+
+// Tokens trickle in at `rate` a second, up to `capacity`. Taking more than
+// there are puts the bucket in debt, which the taker waits out.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, now: Instant) -> TokenBucket {
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    // Takes `n` tokens if they're there.
+    fn try_take(&mut self, n: f64, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= n {
+            self.tokens -= n;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Takes `n` tokens regardless, and returns how long to wait before
+    // acting on them.
+    fn take(&mut self, n: f64, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= n;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            // A slow enough rate puts the wait past what a Duration holds.
+            Duration::try_from_secs_f64(-self.tokens / self.rate).unwrap_or(Duration::MAX)
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+// Everything we know about one address.
+#[derive(Debug)]
+struct Source {
+    connections: usize,
+    new_connections: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    messages: Option<TokenBucket>,
+}
+
+impl Source {
+    fn new(config: &Config, now: Instant) -> Source {
+        let bucket = |rate: Option<f64>| rate.map(|r| TokenBucket::new(r, now));
+        Source {
+            connections: 0,
+            new_connections: bucket(config.connections_per_second),
+            bytes: bucket(config.bytes_per_second),
+            messages: bucket(config.messages_per_second),
+        }
+    }
+
+    // Whether forgetting this source would let it off anything.
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.connections == 0
+            && [
+                &mut self.new_connections,
+                &mut self.bytes,
+                &mut self.messages,
+            ]
+            .into_iter()
+            .flatten()
+            .all(|b| b.is_full(now))
+    }
+}
+
+pub struct Limiter {
+    config: Config,
+    sources: Mutex<HashMap<IpAddr, Arc<Mutex<Source>>>>,
+}
+
+impl Limiter {
+    pub fn new(config: Config) -> Limiter {
+        Limiter {
+            config,
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Decides whether to let in a new connection from `ip`. The permit it
+    // gets counts as one of the address's open connections until dropped.
+    // None means no limits apply.
+    pub fn admit(&self, ip: IpAddr) -> Result<Option<Permit>, Rejection> {
+        if self.config.is_unlimited() || self.config.allowlist.contains(&ip) {
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+        let mut sources = self.sources.lock().unwrap();
+        if sources.len() >= PRUNE_THRESHOLD {
+            sources.retain(|_, s| !s.lock().unwrap().is_idle(now));
+        }
+        let source = sources
+            .entry(ip)
+            .or_insert_with(|| Arc::new(Mutex::new(Source::new(&self.config, now))))
+            .clone();
+        drop(sources);
+
+        let mut s = source.lock().unwrap();
+        if let Some(max) = self.config.max_connections_per_ip {
+            if s.connections >= max {
+                return Err(Rejection::TooManyConnections(max));
+            }
+        }
+        if let Some(bucket) = &mut s.new_connections {
+            if !bucket.try_take(1.0, now) {
+                return Err(Rejection::TooManyNewConnections);
+            }
+        }
+        s.connections += 1;
+        drop(s);
+
+        Ok(Some(Permit { source }))
+    }
+
+    pub fn throttles_bytes(&self) -> bool {
+        self.config.bytes_per_second.is_some()
+    }
+}
+
+// A connection's share of its address's limits.
+pub struct Permit {
+    source: Arc<Mutex<Source>>,
+}
+
+impl Permit {
+    // Waits until the address may send another message.
+    pub fn wait_for_message(&self) {
+        wait_for(
+            &self.source,
+            |s| s.messages.as_mut(),
+            1.0,
+            "limits.messages_throttled",
+        );
+    }
+
+    // Relays everything between `stream` and a new stream for the handler,
+    // holding bytes from the client to the address's byte rate.
+    pub(crate) fn throttle(
+        &self,
+        stream: TcpStream,
+        relays: &crate::Relays,
+    ) -> io::Result<TcpStream> {
+        let (ours, handler_end) = crate::loopback_pair()?;
+
+        let source = self.source.clone();
+        let (from_client, to_handler) = (stream.try_clone()?, ours.try_clone()?);
+        thread::spawn(move || {
+            let result = relay_throttled(&from_client, &to_handler, &source);
+            if result.is_err() {
+                let _ = from_client.shutdown(Shutdown::Both);
+            }
+            let _ = to_handler.shutdown(Shutdown::Write);
+        });
+        relays.spawn(move || {
+            let result = io::copy(&mut &ours, &mut &stream);
+            let how = if result.is_ok() {
+                Shutdown::Write
+            } else {
+                Shutdown::Both
+            };
+            let _ = stream.shutdown(how);
+        });
+
+        Ok(handler_end)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        // Panicking here, while unwinding from a panic elsewhere, would abort.
+        let mut source = self.source.lock().unwrap_or_else(PoisonError::into_inner);
+        source.connections -= 1;
+    }
+}
+
+fn relay_throttled(
+    mut from: &TcpStream,
+    mut to: &TcpStream,
+    source: &Mutex<Source>,
+) -> io::Result<()> {
+    let mut buf = [0; RELAY_BUFFER_SIZE];
+    loop {
+        let n = from.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        wait_for(
+            source,
+            |s| s.bytes.as_mut(),
+            n as f64,
+            "limits.bytes_throttled",
+        );
+        to.write_all(&buf[..n])?;
+    }
+}
+
+// Takes `n` tokens from a source's bucket, sleeping if it's in debt.
+fn wait_for(
+    source: &Mutex<Source>,
+    bucket: fn(&mut Source) -> Option<&mut TokenBucket>,
+    n: f64,
+    counter: &'static str,
+) {
+    let wait = match bucket(&mut source.lock().unwrap()) {
+        Some(b) => b.take(n, Instant::now()),
+        None => return,
+    };
+    if !wait.is_zero() {
+        metrics::increment(counter);
+        thread::sleep(wait);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Limiter, Rejection, TokenBucket};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, start);
+        for _ in 0..10 {
+            assert!(bucket.try_take(1.0, start));
+        }
+        assert!(!bucket.try_take(1.0, start));
+
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.try_take(5.0, later));
+        assert!(!bucket.try_take(1.0, later));
+
+        // Going into debt means waiting until it's paid off.
+        assert_eq!(bucket.take(20.0, later), Duration::from_secs(2));
+        assert!(!bucket.is_full(later + Duration::from_secs(2)));
+        assert!(bucket.is_full(later + Duration::from_secs(3)));
+
+        // However slow the rate, the wait is something we can sleep for.
+        let mut bucket = TokenBucket::new(1e-300, start);
+        assert_eq!(bucket.take(2.0, start), Duration::MAX);
+    }
+
+    #[test]
+    fn test_admit() {
+        let limiter = Limiter::new(Config {
+            max_connections_per_ip: Some(2),
+            allowlist: vec!["10.0.0.1".parse().unwrap()],
+            ..Default::default()
+        });
+        let ip = "192.0.2.1".parse().unwrap();
+
+        let first = limiter.admit(ip).unwrap();
+        let _second = limiter.admit(ip).unwrap();
+        assert_eq!(
+            limiter.admit(ip).err(),
+            Some(Rejection::TooManyConnections(2))
+        );
+        // Other addresses have their own limits.
+        assert!(limiter.admit("192.0.2.2".parse().unwrap()).is_ok());
+
+        drop(first);
+        assert!(limiter.admit(ip).is_ok());
+
+        for _ in 0..10 {
+            assert!(limiter
+                .admit("10.0.0.1".parse().unwrap())
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_admit_rate() {
+        let limiter = Limiter::new(Config {
+            connections_per_second: Some(3.0),
+            ..Default::default()
+        });
+        let ip = "192.0.2.1".parse().unwrap();
+        for _ in 0..3 {
+            assert!(limiter.admit(ip).is_ok());
+        }
+        assert_eq!(
+            limiter.admit(ip).err(),
+            Some(Rejection::TooManyNewConnections)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::{self, BufRead, BufReader, Read};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LineError {
+    #[error("Line is longer than {0} bytes.")]
+    TooLong(usize),
+
+    #[error("Line is not valid UTF-8.")]
+    InvalidUtf8,
+
+    #[error("Error reading line: {0}")]
+    Io(#[from] io::Error),
+}
+
+// Reads newline-terminated lines for the line-oriented services without ever
+// holding more than `max_line_len` bytes of a line, so a client can't run us
+// out of memory by never sending a newline.
+pub struct LineReader<R> {
+    reader: BufReader<R>,
+    max_line_len: usize,
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(reader: R, max_line_len: usize) -> LineReader<R> {
+        LineReader {
+            reader: BufReader::new(reader),
+            max_line_len,
+        }
+    }
+
+    // Returns the next line, including its trailing newline, or `None` at EOF.
+    // Like `BufRead::read_line`, a final line without a newline is still
+    // returned. The newline doesn't count towards the maximum length.
+    pub fn read_line(&mut self) -> Result<Option<String>, LineError> {
+        let mut line = vec![];
+
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if available.is_empty() {
+                break;
+            }
+
+            let (chunk, found_newline) = match available.iter().position(|b| *b == b'\n') {
+                Some(i) => (&available[..=i], true),
+                None => (available, false),
+            };
+            let content_len = line.len() + chunk.len() - found_newline as usize;
+            if content_len > self.max_line_len {
+                return Err(LineError::TooLong(self.max_line_len));
+            }
+
+            line.extend_from_slice(chunk);
+            let consumed = chunk.len();
+            self.reader.consume(consumed);
+            if found_newline {
+                break;
+            }
+        }
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+        String::from_utf8(line)
+            .map(Some)
+            .map_err(|_| LineError::InvalidUtf8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LineError, LineReader};
+    use std::io::Read;
+
+    // Hands out a few bytes per read to exercise lines spanning many reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_reads_lines() {
+        let mut reader = LineReader::new(Trickle(b"one\ntwo\n\nthree"), 5);
+        assert_eq!(reader.read_line().unwrap().unwrap(), "one\n");
+        assert_eq!(reader.read_line().unwrap().unwrap(), "two\n");
+        assert_eq!(reader.read_line().unwrap().unwrap(), "\n");
+        assert_eq!(reader.read_line().unwrap().unwrap(), "three");
+        assert!(reader.read_line().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rejects_long_lines() {
+        let mut reader = LineReader::new(Trickle(b"12345\n123456\n"), 5);
+        assert_eq!(reader.read_line().unwrap().unwrap(), "12345\n");
+        assert!(matches!(reader.read_line(), Err(LineError::TooLong(5))));
+    }
+
+    #[test]
+    fn test_rejects_long_lines_without_newline() {
+        // This would never end if we waited for a newline.
+        let mut reader = LineReader::new(std::io::repeat(b'a'), 1024);
+        assert!(matches!(reader.read_line(), Err(LineError::TooLong(1024))));
+    }
+
+    #[test]
+    fn test_rejects_invalid_utf8() {
+        let mut reader = LineReader::new(Trickle(b"\xff\xfe\n"), 5);
+        assert!(matches!(reader.read_line(), Err(LineError::InvalidUtf8)));
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use thiserror::Error;
+
+use crate::means_to_an_end::Message;
+use crate::Service;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub service: Service,
+    pub address: String,
+    // Connections to keep busy at once.
+    pub clients: usize,
+    // Stop after this long...
+    pub duration: Option<Duration>,
+    // ...or after each client has made this many requests, whichever comes
+    // first. With neither, we run for `DEFAULT_DURATION`.
+    pub requests_per_client: Option<u64>,
+}
+
+pub const DEFAULT_CLIENTS: usize = 10;
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+// A server that takes longer than this to answer has failed the request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a client waits to try again after failing to connect, so a dead
+// server doesn't have us spinning.
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+const SMOKE_TEST_MESSAGE: &[u8] = b"The quick brown fox jumps over the lazy dog.";
+
+#[derive(Debug, Error)]
+enum RequestError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("Unexpected response.")]
+    WrongResponse,
+}
+
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    // Requests that got the response they should have.
+    pub requests: u64,
+    // Requests that didn't, including those we couldn't connect to make.
+    pub errors: u64,
+    pub elapsed: Duration,
+    // How long each successful request took, fastest first.
+    latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    pub fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64()
+    }
+
+    // The latency `percentile` percent of successful requests were at least
+    // as fast as.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests, {} errors in {:?} ({:.0} requests/s)",
+            self.requests,
+            self.errors,
+            self.elapsed,
+            self.throughput()
+        )?;
+        write!(f, "latency:")?;
+        for percentile in [50.0, 90.0, 99.0, 100.0] {
+            match self.latency_percentile(percentile) {
+                Some(latency) => write!(f, " p{}={:?}", percentile, latency)?,
+                None => write!(f, " p{}=-", percentile)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs `config.clients` clients against the server at once and reports how it
+// coped. A client whose request fails counts the error and reconnects.
+pub fn run(config: &Config) -> LoadReport {
+    let duration = match (config.duration, config.requests_per_client) {
+        (None, None) => Some(DEFAULT_DURATION),
+        (duration, _) => duration,
+    };
+    let start = Instant::now();
+    let deadline = duration.map(|d| start + d);
+
+    let reports: Vec<LoadReport> = thread::scope(|scope| {
+        let clients: Vec<_> = (0..config.clients)
+            .map(|client| scope.spawn(move || run_client(config, client, deadline)))
+            .collect();
+        clients.into_iter().map(|c| c.join().unwrap()).collect()
+    });
+
+    let mut report = LoadReport {
+        elapsed: start.elapsed(),
+        ..LoadReport::default()
+    };
+    for client_report in reports {
+        report.requests += client_report.requests;
+        report.errors += client_report.errors;
+        report.latencies.extend(client_report.latencies);
+    }
+    report.latencies.sort();
+    report
+}
+
+fn run_client(config: &Config, client: usize, deadline: Option<Instant>) -> LoadReport {
+    let mut report = LoadReport::default();
+    let mut connection = None;
+
+    loop {
+        // Failed requests count towards the client's share, so that it stops
+        // even if the server's down.
+        let attempts = report.requests + report.errors;
+        if deadline.is_some_and(|d| Instant::now() >= d)
+            || config.requests_per_client == Some(attempts)
+        {
+            break;
+        }
+
+        let stream = match &mut connection {
+            Some(stream) => stream,
+            None => match connect(&config.address) {
+                Ok(stream) => connection.insert(stream),
+                Err(e) => {
+                    debug!("Client {} couldn't connect: {}", client, e);
+                    report.errors += 1;
+                    thread::sleep(RECONNECT_DELAY);
+                    continue;
+                }
+            },
+        };
+
+        // Requests are numbered across all clients so that they differ.
+        let n = attempts * config.clients as u64 + client as u64;
+        let request_start = Instant::now();
+        match make_request(config.service, stream, n) {
+            Ok(()) => {
+                report.requests += 1;
+                report.latencies.push(request_start.elapsed());
+            }
+            Err(e) => {
+                debug!("Client {} request failed: {}", client, e);
+                report.errors += 1;
+                connection = None;
+            }
+        }
+    }
+    report
+}
+
+fn connect(address: &str) -> io::Result<BufReader<TcpStream>> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESPONSE_TIMEOUT))?;
+    Ok(BufReader::new(stream))
+}
+
+// Makes a request and checks the response. `n` varies the request.
+//
+// For the smoke test that's echoing a fixed message, for Prime Time an
+// isPrime request, and for Means to an End an insert and a query for it.
+fn make_request(
+    service: Service,
+    stream: &mut BufReader<TcpStream>,
+    n: u64,
+) -> Result<(), RequestError> {
+    match service {
+        Service::SmokeTest => {
+            stream.get_mut().write_all(SMOKE_TEST_MESSAGE)?;
+            let mut echo = vec![0; SMOKE_TEST_MESSAGE.len()];
+            stream.read_exact(&mut echo)?;
+            if echo != SMOKE_TEST_MESSAGE {
+                return Err(RequestError::WrongResponse);
+            }
+        }
+        Service::PrimeTime => {
+            let request = format!("{{\"method\":\"isPrime\",\"number\":{}}}\n", n);
+            stream.get_mut().write_all(request.as_bytes())?;
+            let mut line = String::new();
+            stream.read_line(&mut line)?;
+            let expected = json::object! {method: "isPrime", prime: primal::is_prime(n)};
+            if json::parse(&line).ok() != Some(expected) {
+                return Err(RequestError::WrongResponse);
+            }
+        }
+        Service::MeansToAnEnd => {
+            let timestamp = n as i32;
+            let price = (n % 1000) as i32;
+            let mut request = Message::Insert { timestamp, price }.to_frame();
+            request.extend(
+                Message::Query {
+                    mintime: timestamp,
+                    maxtime: timestamp,
+                }
+                .to_frame(),
+            );
+            stream.get_mut().write_all(&request)?;
+            let mut mean = [0; 4];
+            stream.read_exact(&mut mean)?;
+            if i32::from_be_bytes(mean) != price {
+                return Err(RequestError::WrongResponse);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::LoadReport;
+    use std::time::Duration;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let report = LoadReport {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            ..LoadReport::default()
+        };
+        assert_eq!(
+            report.latency_percentile(50.0),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            report.latency_percentile(99.0),
+            Some(Duration::from_millis(99))
+        );
+        assert_eq!(
+            report.latency_percentile(100.0),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            report.latency_percentile(0.0),
+            Some(Duration::from_millis(1))
+        );
+
+        assert_eq!(LoadReport::default().latency_percentile(50.0), None);
+    }
+}
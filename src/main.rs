@@ -1,6 +1,13 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
 
-use protohackers::{means_to_an_end, prime_time, smoke_test};
+use protohackers::{check, limits, load, means_to_an_end, prime_time, smoke_test};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -13,39 +20,420 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Echo service.
     SmokeTest {
-        client_or_server: String,
-        client_string: Option<String>,
-        client_destination_url: Option<String>,
+        #[clap(subcommand)]
+        command: SmokeTestCommand,
+    },
+    /// Primality testing service.
+    PrimeTime {
+        #[clap(subcommand)]
+        command: PrimeTimeCommand,
+    },
+    /// Asset price service.
+    MeansToAnEnd {
+        #[clap(subcommand)]
+        command: MeansToAnEndCommand,
+    },
+    /// Run many clients against a server at once and report how it copes.
+    Load {
+        #[clap(value_enum)]
+        service: protohackers::Service,
+        /// Address of the server to load.
+        #[clap(long, value_parser, default_value = protohackers::DEFAULT_SERVER_ADDRESS)]
+        addr: String,
+        /// Clients to run at once.
+        #[clap(long, value_parser, default_value_t = load::DEFAULT_CLIENTS)]
+        clients: usize,
+        /// Seconds to run for. Without this or --requests, runs for 10 seconds.
+        #[clap(long, value_parser = parse_seconds)]
+        duration: Option<Duration>,
+        /// Requests each client makes before stopping.
+        #[clap(long, value_parser)]
+        requests: Option<u64>,
+    },
+    /// Check a server behaves the way the Protohackers grader expects.
+    Check {
+        #[clap(value_enum)]
+        service: protohackers::Service,
+        /// Address of the server to check, e.g. 127.0.0.1:5001.
+        #[clap(value_parser)]
+        addr: String,
+    },
+}
+
+#[derive(Args)]
+struct ServerArgs {
+    /// Serve over TLS with this PEM certificate chain.
+    #[clap(long, value_parser, requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for --tls-cert.
+    #[clap(long, value_parser, requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+    /// Only accept TLS clients with a certificate signed by a CA in this PEM file.
+    #[clap(long, value_parser, requires = "tls-cert")]
+    tls_client_ca: Option<PathBuf>,
+    /// Expect a PROXY protocol (v1 or v2) header ahead of every connection.
+    #[clap(long)]
+    proxy_protocol: bool,
+    /// Connections a single IP address may have open at once.
+    #[clap(long, value_parser)]
+    max_connections_per_ip: Option<usize>,
+    /// New connections a single IP address may make per second.
+    #[clap(long, value_parser = parse_rate)]
+    connections_per_second: Option<f64>,
+    /// Bytes per second to read from a single IP address, across its connections.
+    #[clap(long, value_parser = parse_rate)]
+    bytes_per_second: Option<f64>,
+    /// Messages per second to handle from a single IP address, across its connections.
+    #[clap(long, value_parser = parse_rate)]
+    messages_per_second: Option<f64>,
+    /// An IP address the limits above don't apply to. May be given more than once.
+    #[clap(long = "allow-ip", value_parser)]
+    allowlist: Vec<IpAddr>,
+    /// Serve the admin endpoint on this port, on localhost only.
+    #[clap(long, value_parser)]
+    admin_port: Option<usize>,
+}
+
+impl ServerArgs {
+    fn options(
+        self,
+        service: protohackers::Service,
+        port: Option<usize>,
+    ) -> protohackers::ServerOptions {
+        let tls = match (self.tls_cert, self.tls_key) {
+            (Some(cert), Some(key)) => Some(exit_on_error(protohackers::tls::server_config(
+                &cert,
+                &key,
+                self.tls_client_ca.as_deref(),
+            ))),
+            _ => None,
+        };
+        protohackers::ServerOptions {
+            service: Some(service),
+            port,
+            proxy_protocol: self.proxy_protocol,
+            tls,
+            limits: limits::Config {
+                max_connections_per_ip: self.max_connections_per_ip,
+                connections_per_second: self.connections_per_second,
+                bytes_per_second: self.bytes_per_second,
+                messages_per_second: self.messages_per_second,
+                allowlist: self.allowlist,
+            },
+            admin_port: self.admin_port,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Args)]
+struct ClientArgs {
+    /// Address of the server to talk to.
+    #[clap(long, value_parser, default_value = protohackers::DEFAULT_SERVER_ADDRESS)]
+    addr: String,
+}
+
+#[derive(Subcommand)]
+enum SmokeTestCommand {
+    Server {
+        #[clap(flatten)]
+        server: ServerArgs,
+    },
+    /// Send a message and check it's echoed back.
+    Client {
+        #[clap(flatten)]
+        client: ClientArgs,
+        /// What to send.
+        #[clap(long, value_parser, default_value = "Hello world!")]
+        message: String,
+        /// Send the contents of this file instead, or stdin if it's `-`.
+        #[clap(long, value_parser, conflicts_with = "message")]
+        input: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PrimeTimeCommand {
+    Server {
+        #[clap(flatten)]
+        server: ServerArgs,
+        /// How requests and responses are framed.
+        #[clap(long, value_enum, default_value = "protohackers")]
+        protocol: prime_time::Protocol,
+        /// Whether to reject requests with unexpected or duplicate fields.
+        #[clap(long, value_enum, default_value = "lenient")]
+        validation: prime_time::Validation,
+        /// Describe what's wrong with malformed requests instead of replying `ERROR`.
+        #[clap(long)]
+        verbose_errors: bool,
+        /// Longest request line to accept, in bytes.
+        #[clap(long, value_parser, default_value_t = prime_time::DEFAULT_MAX_LINE_LENGTH)]
+        max_line_length: usize,
+        /// Threads evaluating requests across all connections.
+        #[clap(long, value_parser, default_value_t = prime_time::DEFAULT_COMPUTE_THREADS)]
+        compute_threads: usize,
+        /// Requests a single connection may have in flight at once.
+        #[clap(long, value_parser, default_value_t = prime_time::DEFAULT_MAX_IN_FLIGHT)]
+        max_in_flight: usize,
+        /// Primality results to remember across connections. 0 turns the cache off.
+        #[clap(long, value_parser, default_value_t = prime_time::DEFAULT_CACHE_CAPACITY)]
+        cache_capacity: usize,
+        /// Numbers up to this are answered from a sieve built at startup. 0 turns it off.
+        #[clap(long, value_parser, default_value_t = prime_time::DEFAULT_SIEVE_LIMIT)]
+        sieve_limit: usize,
+        /// Also accept length-prefixed MessagePack requests from clients that start with one.
+        #[clap(long)]
+        binary_transport: bool,
+        /// Digits in the longest number to test for primality, unless a small factor gives it away.
+        #[clap(long, value_parser, default_value_t = prime_time::DEFAULT_MAX_DIGITS)]
+        max_digits: u32,
+    },
+    /// Ask about some numbers, or send request lines from stdin if there are none.
+    Client {
+        #[clap(flatten)]
+        client: ClientArgs,
+        /// The method to ask about the numbers with.
+        #[clap(long, value_parser, default_value = "isPrime")]
+        method: String,
+        numbers: Vec<String>,
     },
-    PrimeTime,
-    MeansToAnEnd,
+}
+
+#[derive(Subcommand)]
+enum MeansToAnEndCommand {
+    Server {
+        #[clap(flatten)]
+        server: ServerArgs,
+        /// Maximum number of prices a single session may store.
+        #[clap(long, value_parser)]
+        max_entries_per_session: Option<usize>,
+        /// Maximum bytes of prices stored across all sessions.
+        #[clap(long, value_parser)]
+        max_total_memory: Option<usize>,
+        /// What to do with an insert that would exceed a limit.
+        #[clap(long, value_enum, default_value = "reject")]
+        limit_policy: means_to_an_end::LimitPolicy,
+        /// How to handle frames with an unknown message type.
+        #[clap(long, value_enum, default_value = "reject")]
+        garbage_policy: means_to_an_end::codec::GarbagePolicy,
+        /// What to do when a client sends a message with an unknown type.
+        #[clap(long, value_enum, default_value = "ignore")]
+        invalid_message_policy: means_to_an_end::InvalidMessagePolicy,
+        /// What an insert does when a price already exists at its timestamp.
+        #[clap(long, value_enum, default_value = "overwrite")]
+        duplicate_policy: means_to_an_end::DuplicatePolicy,
+    },
+    /// Send commands like `insert 1000 100` or `query 900 1100` in one session.
+    ///
+    /// Without any commands or a script, reads commands from stdin.
+    Client {
+        #[clap(flatten)]
+        client: ClientArgs,
+        /// Run the commands in this file, one per line.
+        #[clap(long, value_parser, conflicts_with = "commands")]
+        script: Option<PathBuf>,
+        commands: Vec<String>,
+    },
+}
+
+// Reads a positive number of seconds, which may have a fractional part.
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|_| format!("'{}' isn't a number", s))?;
+    if !(seconds.is_finite() && seconds > 0.0) {
+        return Err("must be a positive number of seconds".to_string());
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+// Reads a rate, which has to be positive to ever let anything through.
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|_| format!("'{}' isn't a number", s))?;
+    if !(rate.is_finite() && rate > 0.0) {
+        return Err("must be a positive number per second".to_string());
+    }
+    Ok(rate)
+}
+
+// Clients exit non-zero on failure, after saying why.
+fn exit_on_error<T, E: Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
 }
 
 fn main() {
-    env_logger::init();
+    protohackers::admin::init_logging();
     let args = Cli::parse();
 
     match args.command {
         Commands::SmokeTest {
-            client_or_server,
-            client_string,
-            client_destination_url,
-        } => match (client_or_server.as_str(), client_string) {
-            ("server", _) => {
-                protohackers::run_server(args.port, 5, smoke_test::handle_connection);
-            }
-            ("client", Some(client_string)) => {
-                smoke_test::run_client(client_destination_url, client_string.as_bytes())
+            command: SmokeTestCommand::Server { server },
+        } => {
+            protohackers::run_server(
+                server.options(protohackers::Service::SmokeTest, args.port),
+                smoke_test::handle_connection,
+            );
+        }
+        Commands::SmokeTest {
+            command:
+                SmokeTestCommand::Client {
+                    client,
+                    message,
+                    input,
+                },
+        } => {
+            let payload = match input {
+                Some(input) => exit_on_error(
+                    smoke_test::read_payload(&input)
+                        .map_err(|e| format!("Couldn't read {}: {}", input.display(), e)),
+                ),
+                None => message.into_bytes(),
+            };
+            let report = exit_on_error(smoke_test::run_client(
+                &client.addr,
+                &payload,
+                &mut io::stdout(),
+            ));
+            eprintln!("{}", report);
+        }
+        Commands::PrimeTime {
+            command:
+                PrimeTimeCommand::Server {
+                    server: server_args,
+                    protocol,
+                    validation,
+                    verbose_errors,
+                    max_line_length,
+                    compute_threads,
+                    max_in_flight,
+                    cache_capacity,
+                    sieve_limit,
+                    binary_transport,
+                    max_digits,
+                },
+        } => {
+            let server = prime_time::Server::new(prime_time::Config {
+                protocol,
+                validation,
+                verbose_errors,
+                max_line_length,
+                compute_threads,
+                max_in_flight,
+                cache_capacity,
+                sieve_limit,
+                binary_transport,
+                max_digits,
+            });
+            protohackers::run_server(
+                server_args.options(protohackers::Service::PrimeTime, args.port),
+                move |stream, connection| server.handle_connection(stream, connection),
+            )
+        }
+        Commands::PrimeTime {
+            command:
+                PrimeTimeCommand::Client {
+                    client,
+                    method,
+                    numbers,
+                },
+        } => {
+            let result = if numbers.is_empty() {
+                prime_time::client::run_client(
+                    &client.addr,
+                    BufReader::new(io::stdin()).lines(),
+                    &mut io::stdout(),
+                )
+            } else {
+                let requests = numbers
+                    .iter()
+                    .map(|n| Ok(prime_time::client::request_line(&method, n)));
+                prime_time::client::run_client(&client.addr, requests, &mut io::stdout())
+            };
+            exit_on_error(result);
+        }
+        Commands::MeansToAnEnd {
+            command:
+                MeansToAnEndCommand::Server {
+                    server: server_args,
+                    max_entries_per_session,
+                    max_total_memory,
+                    limit_policy,
+                    garbage_policy,
+                    invalid_message_policy,
+                    duplicate_policy,
+                },
+        } => {
+            let server = means_to_an_end::Server::new(means_to_an_end::Config {
+                max_entries_per_session,
+                max_total_memory,
+                limit_policy,
+                garbage_policy,
+                invalid_message_policy,
+                duplicate_policy,
+            });
+            protohackers::run_server(
+                server_args.options(protohackers::Service::MeansToAnEnd, args.port),
+                move |stream, connection| server.handle_connection(stream, connection),
+            )
+        }
+        Commands::MeansToAnEnd {
+            command:
+                MeansToAnEndCommand::Client {
+                    client,
+                    script,
+                    commands,
+                },
+        } => {
+            let result = match script {
+                Some(script) => {
+                    let script = exit_on_error(
+                        File::open(&script)
+                            .map_err(|e| format!("Couldn't read {}: {}", script.display(), e)),
+                    );
+                    means_to_an_end::client::run_repl(
+                        &client.addr,
+                        BufReader::new(script),
+                        &mut io::stdout(),
+                        false,
+                    )
+                }
+                None if commands.is_empty() => means_to_an_end::client::run_repl(
+                    &client.addr,
+                    io::stdin().lock(),
+                    &mut io::stdout(),
+                    io::stdin().is_terminal(),
+                ),
+                None => {
+                    means_to_an_end::client::run_client(&client.addr, &commands, &mut io::stdout())
+                }
+            };
+            exit_on_error(result);
+        }
+        Commands::Load {
+            service,
+            addr,
+            clients,
+            duration,
+            requests,
+        } => {
+            let report = load::run(&load::Config {
+                service,
+                address: addr,
+                clients,
+                duration,
+                requests_per_client: requests,
+            });
+            println!("{}", report);
+        }
+        Commands::Check { service, addr } => {
+            let report = check::run(service, &addr);
+            println!("{}", report);
+            if !report.passed() {
+                process::exit(1);
             }
-            ("client", None) => smoke_test::run_client(client_destination_url, b"Hello world!"),
-            _ => panic!("Invalid smoketest argument '{}'.", client_or_server),
-        },
-        Commands::PrimeTime => {
-            protohackers::run_server(args.port, 5, prime_time::handle_connection)
-        }
-        Commands::MeansToAnEnd => {
-            protohackers::run_server(args.port, 5, means_to_an_end::handle_connection)
         }
     }
 }
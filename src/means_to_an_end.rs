@@ -1,45 +1,160 @@
+use std::collections::VecDeque;
+use std::fmt;
 use std::io::{Read, Write};
+use std::mem;
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::warn;
 use thiserror::Error;
 
+use crate::metrics;
+use crate::Connection;
+
+pub mod client;
+pub mod codec;
+
+use codec::{GarbagePolicy, MessageDecoder, ResponseEncoder};
+
 #[derive(Debug, Error)]
 pub enum MeansToAnEndError {
-    #[error("Message type must be 'I' or 'Q'.")]
-    InvalidMessageType,
+    #[error("Message type must be one of 'I', 'A', 'Q', 'C', 'D' or 'R', got bytes {0:02x?}.")]
+    InvalidMessageType(Vec<u8>),
+
+    #[error("A price already exists at timestamp {0}.")]
+    DuplicateTimestamp(i32),
+
+    #[error("Insert would exceed the {0}.")]
+    LimitExceeded(Limit),
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Limit {
+    EntriesPerSession(usize),
+    TotalMemory(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::EntriesPerSession(max) => write!(f, "per-session limit of {} entries", max),
+            Limit::TotalMemory(max) => write!(f, "total memory limit of {} bytes", max),
+        }
+    }
+}
+
+// What to do with an insert that would push a session past one of its limits.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum LimitPolicy {
+    // Drop the insert and carry on.
+    Reject,
+    // Make room by removing the session's oldest inserted price.
+    EvictOldest,
+    // Close the connection.
+    Disconnect,
+}
 
-    #[error("If this error is being returned here, there is a major bug.")]
-    NotPossible,
+// What to do when a client sends bytes that aren't a message we understand.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum InvalidMessagePolicy {
+    // Skip over them, as the protocol allows.
+    Ignore,
+    // Close the connection.
+    Disconnect,
+    // Reply with an error frame (see `ResponseEncoder::encode_error`) and carry on.
+    ErrorFrame,
+}
+
+// What a plain insert does when the session already has a price at that
+// timestamp. The protocol leaves this undefined.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    // Replace the existing price.
+    Overwrite,
+    // Keep the existing price and quietly drop the new one.
+    KeepFirst,
+    // Keep the existing price and report the new one as an error.
+    Reject,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub max_entries_per_session: Option<usize>,
+    // Bytes of asset prices stored across all sessions on this server.
+    pub max_total_memory: Option<usize>,
+    pub limit_policy: LimitPolicy,
+    pub garbage_policy: GarbagePolicy,
+    pub invalid_message_policy: InvalidMessagePolicy,
+    pub duplicate_policy: DuplicatePolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_entries_per_session: None,
+            max_total_memory: None,
+            limit_policy: LimitPolicy::Reject,
+            garbage_policy: GarbagePolicy::Reject,
+            invalid_message_policy: InvalidMessagePolicy::Ignore,
+            duplicate_policy: DuplicatePolicy::Overwrite,
+        }
+    }
+}
+
+pub struct Server {
+    config: Config,
+    memory_in_use: AtomicUsize,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Message {
-    Insert { timestamp: i32, price: i32 },
-    Query { mintime: i32, maxtime: i32 },
+    Insert {
+        timestamp: i32,
+        price: i32,
+    },
+    // Like `Insert`, but never replaces an existing price.
+    InsertIfAbsent {
+        timestamp: i32,
+        price: i32,
+    },
+    Delete {
+        timestamp: i32,
+    },
+    DeleteRange {
+        mintime: i32,
+        maxtime: i32,
+    },
+    Query {
+        mintime: i32,
+        maxtime: i32,
+    },
+    Candles {
+        mintime: i32,
+        maxtime: i32,
+        width: i32,
+    },
+}
+
+// One time bucket's worth of prices, as returned for a `Message::Candles`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Candle {
+    // The first timestamp covered by this bucket.
+    pub start: i32,
+    pub open: i32,
+    pub high: i32,
+    pub low: i32,
+    pub close: i32,
+    pub mean: i32,
+    pub count: i32,
 }
 
 impl Message {
     fn from_network_bytes(bytes: [u8; 9]) -> Result<Self, MeansToAnEndError> {
-        let mut message_type: u8 = 0;
-        let mut first_i32: [u8; 4] = [0; 4];
-        let mut second_i32: [u8; 4] = [0; 4];
-
-        // Extract bytes to each part of the message.
-        for (index, byte) in bytes.iter().enumerate() {
-            match index {
-                0 => {
-                    message_type = *byte;
-                }
-                (1..=4) => {
-                    first_i32[index - 1] = *byte;
-                }
-                (5..=8) => {
-                    second_i32[index - 5] = *byte;
-                }
-                _ => {
-                    return Err(MeansToAnEndError::NotPossible);
-                }
-            }
-        }
+        // Destructuring the array means there's no way for this to go out of
+        // bounds: a frame is always exactly one type byte and two i32s.
+        let [message_type, a0, a1, a2, a3, b0, b1, b2, b3] = bytes;
+        let first_i32 = [a0, a1, a2, a3];
+        let second_i32 = [b0, b1, b2, b3];
 
         // Network-byte order is big endian. The method `from_be_bytes`
         // interprets bytes as big endian (hence the `be`).
@@ -48,37 +163,62 @@ impl Message {
                 timestamp: i32::from_be_bytes(first_i32),
                 price: i32::from_be_bytes(second_i32),
             }),
+            'A' => Ok(Message::InsertIfAbsent {
+                timestamp: i32::from_be_bytes(first_i32),
+                price: i32::from_be_bytes(second_i32),
+            }),
+            // The second i32 is unused for single deletes.
+            'D' => Ok(Message::Delete {
+                timestamp: i32::from_be_bytes(first_i32),
+            }),
+            'R' => Ok(Message::DeleteRange {
+                mintime: i32::from_be_bytes(first_i32),
+                maxtime: i32::from_be_bytes(second_i32),
+            }),
             'Q' => Ok(Message::Query {
                 mintime: i32::from_be_bytes(first_i32),
                 maxtime: i32::from_be_bytes(second_i32),
             }),
-            _ => Err(MeansToAnEndError::InvalidMessageType),
+            _ => Err(MeansToAnEndError::InvalidMessageType(bytes.to_vec())),
         }
     }
 
-    pub fn to_network_bytes(&self) -> [u8; 9] {
-        match self {
-            Message::Insert { timestamp, price } => {
-                let mut bytes: [u8; 9] = [0; 9];
-                bytes[0] = b'I';
-                Message::i32_to_network_bytes(*timestamp, &mut bytes[1..5]);
-                Message::i32_to_network_bytes(*price, &mut bytes[5..9]);
-                bytes
-            }
-            Message::Query { mintime, maxtime } => {
-                let mut bytes: [u8; 9] = [0; 9];
-                bytes[0] = b'Q';
-                Message::i32_to_network_bytes(*mintime, &mut bytes[1..5]);
-                Message::i32_to_network_bytes(*maxtime, &mut bytes[5..9]);
-                bytes
-            }
+    // Candle queries carry a bucket width on top of the usual two i32s, so
+    // they have a frame of their own.
+    fn candles_from_network_bytes(bytes: [u8; 13]) -> Self {
+        let [_, a0, a1, a2, a3, b0, b1, b2, b3, c0, c1, c2, c3] = bytes;
+        Message::Candles {
+            mintime: i32::from_be_bytes([a0, a1, a2, a3]),
+            maxtime: i32::from_be_bytes([b0, b1, b2, b3]),
+            width: i32::from_be_bytes([c0, c1, c2, c3]),
         }
     }
 
-    fn i32_to_network_bytes(an_i32: i32, buf: &mut [u8]) {
-        for (i, x) in an_i32.to_be_bytes().iter().enumerate() {
-            buf[i] = *x;
+    fn is_type_byte(byte: u8) -> bool {
+        matches!(byte, b'I' | b'A' | b'D' | b'R' | b'Q' | b'C')
+    }
+
+    // The message as it goes on the wire: its type, then its fields as
+    // big-endian i32s. Every message but `Candles` takes nine bytes, padded
+    // with zeros if it has fewer fields.
+    pub fn to_frame(&self) -> Vec<u8> {
+        let (message_type, fields) = match *self {
+            Message::Insert { timestamp, price } => (b'I', vec![timestamp, price]),
+            Message::InsertIfAbsent { timestamp, price } => (b'A', vec![timestamp, price]),
+            Message::Delete { timestamp } => (b'D', vec![timestamp, 0]),
+            Message::DeleteRange { mintime, maxtime } => (b'R', vec![mintime, maxtime]),
+            Message::Query { mintime, maxtime } => (b'Q', vec![mintime, maxtime]),
+            Message::Candles {
+                mintime,
+                maxtime,
+                width,
+            } => (b'C', vec![mintime, maxtime, width]),
+        };
+        let mut bytes = vec![message_type];
+        for field in fields {
+            bytes.extend(field.to_be_bytes());
         }
+        bytes
     }
 }
 
@@ -102,18 +242,28 @@ impl Message {
  * - Glue. Run tests.
  */
 
-struct Session {
+struct Session<'a> {
     db: AssetPriceDB,
+    memory_in_use: &'a AtomicUsize,
 }
 
-impl Session {
-    pub fn new() -> Session {
+impl<'a> Session<'a> {
+    pub fn new(memory_in_use: &'a AtomicUsize) -> Session<'a> {
         Session {
             db: AssetPriceDB::new(),
+            memory_in_use,
         }
     }
 }
 
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        // Hand this session's share of the memory budget back to the server.
+        self.memory_in_use
+            .fetch_sub(self.db.memory_usage(), Ordering::SeqCst);
+    }
+}
+
 // The derivation of *Eq and *Ord below will compare timestamp first
 // then price, which is fine. We want this to be ordered by timestamp.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -123,19 +273,50 @@ struct AssetPrice {
 }
 
 struct AssetPriceDB {
-    asset_prices: Vec<AssetPrice>,
+    asset_prices: VecDeque<AssetPrice>,
 }
 
 impl AssetPriceDB {
     pub fn new() -> AssetPriceDB {
         AssetPriceDB {
-            asset_prices: vec![],
+            asset_prices: VecDeque::new(),
         }
     }
 
     fn insert(&mut self, timestamp: i32, price: i32) {
         self.remove_if_exists(timestamp);
-        self.asset_prices.push(AssetPrice { timestamp, price });
+        self.asset_prices.push_back(AssetPrice { timestamp, price });
+    }
+
+    fn contains(&self, timestamp: i32) -> bool {
+        self.asset_prices.iter().any(|v| v.timestamp == timestamp)
+    }
+
+    fn len(&self) -> usize {
+        self.asset_prices.len()
+    }
+
+    // How many prices there's room for without allocating any more.
+    fn capacity(&self) -> usize {
+        self.asset_prices.capacity()
+    }
+
+    // Makes room for at least `additional` more prices than there are, and
+    // returns how much more room there is than before.
+    fn grow(&mut self, additional: usize) -> usize {
+        let capacity = self.capacity();
+        self.asset_prices.reserve_exact(additional);
+        self.capacity() - capacity
+    }
+
+    // Prices are appended as they're inserted, so the oldest is at the front.
+    fn remove_oldest(&mut self) -> bool {
+        self.asset_prices.pop_front().is_some()
+    }
+
+    // Counts the room allocated for prices, used or not.
+    fn memory_usage(&self) -> usize {
+        self.capacity() * mem::size_of::<AssetPrice>()
     }
 
     fn query(&self, mintime: i32, maxtime: i32) -> i32 {
@@ -145,7 +326,7 @@ impl AssetPriceDB {
 
         let mut num_assets = 0;
         let mut total_asset_price = 0i64;
-        for (_, v) in self.asset_prices.iter().enumerate() {
+        for v in self.asset_prices.iter() {
             if mintime <= v.timestamp && v.timestamp <= maxtime {
                 total_asset_price += v.price as i64;
                 num_assets += 1;
@@ -159,56 +340,286 @@ impl AssetPriceDB {
         }
     }
 
+    // Splits [mintime, maxtime] into buckets `width` wide, starting at mintime,
+    // and summarises the prices in each. Empty buckets are left out.
+    fn candles(&self, mintime: i32, maxtime: i32, width: i32) -> Vec<Candle> {
+        if maxtime < mintime || width <= 0 {
+            return vec![];
+        }
+
+        let mut in_range: Vec<&AssetPrice> = self
+            .asset_prices
+            .iter()
+            .filter(|v| mintime <= v.timestamp && v.timestamp <= maxtime)
+            .collect();
+        in_range.sort();
+
+        let bucket_of = |timestamp: i32| (timestamp as i64 - mintime as i64) / width as i64;
+
+        let mut candles = vec![];
+        let mut remaining = &in_range[..];
+        while let Some(first) = remaining.first() {
+            let bucket_len = remaining
+                .iter()
+                .take_while(|v| bucket_of(v.timestamp) == bucket_of(first.timestamp))
+                .count();
+            let (bucket, rest) = remaining.split_at(bucket_len);
+            remaining = rest;
+
+            let total: i64 = bucket.iter().map(|v| v.price as i64).sum();
+            candles.push(Candle {
+                start: (mintime as i64 + bucket_of(first.timestamp) * width as i64) as i32,
+                open: first.price,
+                high: bucket.iter().map(|v| v.price).max().unwrap(),
+                low: bucket.iter().map(|v| v.price).min().unwrap(),
+                close: bucket[bucket.len() - 1].price,
+                mean: (total / bucket.len() as i64) as i32,
+                count: bucket.len() as i32,
+            });
+        }
+        candles
+    }
+
     fn remove_if_exists(&mut self, timestamp: i32) {
-        for (i, v) in self.asset_prices.iter().enumerate() {
-            if v.timestamp == timestamp {
-                self.asset_prices.remove(i);
-                break;
+        self.remove_range(timestamp, timestamp);
+    }
+
+    // Removes every price with a timestamp in [mintime, maxtime] and returns
+    // how many were removed.
+    fn remove_range(&mut self, mintime: i32, maxtime: i32) -> usize {
+        let len_before = self.asset_prices.len();
+        self.asset_prices
+            .retain(|v| v.timestamp < mintime || maxtime < v.timestamp);
+        len_before - self.asset_prices.len()
+    }
+}
+
+impl Server {
+    pub fn new(config: Config) -> Server {
+        Server {
+            config,
+            memory_in_use: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn handle_connection(&self, mut stream: TcpStream, connection: Connection) {
+        let mut session = Session::new(&self.memory_in_use);
+        let mut decoder = MessageDecoder::new(self.config.garbage_policy);
+        let mut read_buf = [0; 4096];
+        let mut responses = vec![];
+
+        loop {
+            match stream.read(&mut read_buf) {
+                Ok(0) => {
+                    // Connection's closed.
+                    break;
+                }
+                Ok(n) => {
+                    connection.record_received(n);
+                    decoder.extend(&read_buf[..n]);
+                    let keep_going = self.handle_messages(
+                        &mut decoder,
+                        &mut session,
+                        &mut responses,
+                        &connection,
+                    );
+                    connection.set_state(format!("{} prices stored", session.db.len()));
+
+                    // Answer every query in this batch with a single write.
+                    if !responses.is_empty() {
+                        if stream.write_all(&responses).is_err() {
+                            break;
+                        }
+                        connection.record_sent(responses.len());
+                        responses.clear();
+                    }
+
+                    if !keep_going {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    // Error reading from the stream.
+                    // Let's just close the connection.
+                    break;
+                }
             }
         }
     }
-}
 
-pub fn handle_connection(mut stream: TcpStream) {
-    let mut session = Session::new();
+    // Handles every whole message the decoder has buffered, appending responses
+    // to `responses`. Returns false if the connection should be closed.
+    fn handle_messages(
+        &self,
+        decoder: &mut MessageDecoder,
+        session: &mut Session,
+        responses: &mut Vec<u8>,
+        connection: &Connection,
+    ) -> bool {
+        while let Some(message) = decoder.decode() {
+            connection.wait_for_message();
+            match message {
+                Ok(m) => {
+                    if let Err(e) = self.handle_message(responses, session, m) {
+                        warn!("{}: {}", connection.client, e);
+                        if let MeansToAnEndError::LimitExceeded(_) = e {
+                            if self.config.limit_policy == LimitPolicy::Disconnect {
+                                return false;
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("{}: {}", connection.client, e);
+                    metrics::increment("means_to_an_end.invalid_messages");
+                    match self.config.invalid_message_policy {
+                        InvalidMessagePolicy::Ignore => {}
+                        InvalidMessagePolicy::Disconnect => return false,
+                        InvalidMessagePolicy::ErrorFrame => {
+                            ResponseEncoder::encode_error(&e, responses)
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
 
-    loop {
-        let mut buf: [u8; 9] = [0; 9];
-        match stream.read_exact(&mut buf) {
-            Ok(_) => {
-                let message = Message::from_network_bytes(buf);
-                if let Ok(m) = message {
-                    handle_message(&mut stream, &mut session, m);
-                } // If the message doesn't parse, ignore it.
+    fn handle_message(
+        &self,
+        responses: &mut Vec<u8>,
+        session: &mut Session,
+        message: Message,
+    ) -> Result<(), MeansToAnEndError> {
+        match message {
+            Message::Insert { timestamp, price } => {
+                self.insert(session, timestamp, price, self.config.duplicate_policy)?;
             }
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                // Connection's closed.
-                break;
+            Message::InsertIfAbsent { timestamp, price } => {
+                self.insert(session, timestamp, price, DuplicatePolicy::KeepFirst)?;
             }
-            Err(_) => {
-                // Error reading from the stream.
-                // Let's just close the connection.
-                break;
+            Message::Delete { timestamp } => {
+                self.delete(session, timestamp, timestamp);
+            }
+            Message::DeleteRange { mintime, maxtime } => {
+                self.delete(session, mintime, maxtime);
+            }
+            Message::Query { mintime, maxtime } => {
+                let mean = session.db.query(mintime, maxtime);
+                ResponseEncoder::encode_mean(mean, responses);
+            }
+            Message::Candles {
+                mintime,
+                maxtime,
+                width,
+            } => {
+                let candles = session.db.candles(mintime, maxtime, width);
+                ResponseEncoder::encode_candles(&candles, responses);
             }
         }
+        Ok(())
     }
-}
 
-fn handle_message(stream: &mut TcpStream, session: &mut Session, message: Message) {
-    match message {
-        Message::Insert { timestamp, price } => {
+    fn insert(
+        &self,
+        session: &mut Session,
+        timestamp: i32,
+        price: i32,
+        duplicate_policy: DuplicatePolicy,
+    ) -> Result<(), MeansToAnEndError> {
+        if session.db.contains(timestamp) {
+            match duplicate_policy {
+                // Overwriting an existing timestamp doesn't take up any more room.
+                DuplicatePolicy::Overwrite => session.db.insert(timestamp, price),
+                DuplicatePolicy::KeepFirst => {}
+                DuplicatePolicy::Reject => {
+                    metrics::increment("means_to_an_end.duplicates_rejected");
+                    return Err(MeansToAnEndError::DuplicateTimestamp(timestamp));
+                }
+            }
+        } else {
+            self.make_room(session)?;
             session.db.insert(timestamp, price);
         }
-        Message::Query { mintime, maxtime } => {
-            let mean = session.db.query(mintime, maxtime);
-            stream.write_all(&mean.to_be_bytes()).unwrap();
+        Ok(())
+    }
+
+    // The room the prices took stays the session's, for whatever it inserts
+    // next.
+    fn delete(&self, session: &mut Session, mintime: i32, maxtime: i32) {
+        session.db.remove_range(mintime, maxtime);
+    }
+
+    // Makes sure the session has room for one more entry, growing its storage
+    // or evicting its oldest entries if the limits and policy allow it.
+    fn make_room(&self, session: &mut Session) -> Result<(), MeansToAnEndError> {
+        loop {
+            let limit = match self.config.max_entries_per_session {
+                Some(max) if session.db.len() >= max => Limit::EntriesPerSession(max),
+                _ if session.db.len() < session.db.capacity() => return Ok(()),
+                _ => match self.grow(session) {
+                    Ok(()) => return Ok(()),
+                    Err(limit) => limit,
+                },
+            };
+
+            metrics::increment("means_to_an_end.limit_exceeded");
+            if self.config.limit_policy == LimitPolicy::EvictOldest && session.db.remove_oldest() {
+                metrics::increment("means_to_an_end.evictions");
+            } else {
+                metrics::increment("means_to_an_end.inserts_rejected");
+                return Err(MeansToAnEndError::LimitExceeded(limit));
+            }
+        }
+    }
+
+    // Doubles the room the session has for entries, or grows it by just the
+    // one if that's all the memory limit allows.
+    fn grow(&self, session: &mut Session) -> Result<(), Limit> {
+        let entry_size = mem::size_of::<AssetPrice>();
+        let capacity = session.db.capacity();
+        let doubled = match self.config.max_entries_per_session {
+            Some(max) => (capacity * 2).clamp(1, max),
+            None => (capacity * 2).max(1),
+        };
+
+        let mut result = Ok(());
+        for additional in [doubled - capacity, 1] {
+            result = self.reserve_memory(additional * entry_size);
+            if result.is_ok() {
+                // We may have been given more room than we asked for.
+                let grown = session.db.grow(additional);
+                self.memory_in_use
+                    .fetch_add((grown - additional) * entry_size, Ordering::SeqCst);
+                break;
+            }
+        }
+        result
+    }
+
+    fn reserve_memory(&self, bytes: usize) -> Result<(), Limit> {
+        match self.config.max_total_memory {
+            None => {
+                self.memory_in_use.fetch_add(bytes, Ordering::SeqCst);
+                Ok(())
+            }
+            Some(max) => self
+                .memory_in_use
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                    (used + bytes <= max).then_some(used + bytes)
+                })
+                .map(|_| ())
+                .map_err(|_| Limit::TotalMemory(max)),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Message;
+    use std::sync::atomic::Ordering;
+
+    use super::{Config, DuplicatePolicy, Message, Server, Session};
 
     #[test]
     fn test_message_network_byte_serde() {
@@ -220,7 +631,38 @@ mod test {
         // Serialize then deserialize and make sure it still matches.
         assert_eq!(
             message,
-            Message::from_network_bytes(message.to_network_bytes()).unwrap()
+            Message::from_network_bytes(message.to_frame().try_into().unwrap()).unwrap()
+        );
+
+        let candles = Message::Candles {
+            mintime: -5,
+            maxtime: 1000,
+            width: 10,
+        };
+        assert_eq!(
+            candles,
+            Message::candles_from_network_bytes(candles.to_frame().try_into().unwrap())
         );
     }
+
+    #[test]
+    fn test_memory_in_use_counts_capacity() {
+        let server = Server::new(Config::default());
+        let mut session = Session::new(&server.memory_in_use);
+        for timestamp in 0..5 {
+            server
+                .insert(&mut session, timestamp, 1, DuplicatePolicy::Overwrite)
+                .unwrap();
+        }
+        let in_use = server.memory_in_use.load(Ordering::SeqCst);
+        assert_eq!(in_use, session.db.memory_usage());
+        assert!(session.db.capacity() >= 5);
+
+        // Deleting prices doesn't give back the room they took.
+        server.delete(&mut session, 0, 4);
+        assert_eq!(server.memory_in_use.load(Ordering::SeqCst), in_use);
+
+        drop(session);
+        assert_eq!(server.memory_in_use.load(Ordering::SeqCst), 0);
+    }
 }
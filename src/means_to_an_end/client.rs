@@ -0,0 +1,314 @@
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::net::TcpStream;
+
+use thiserror::Error;
+
+use super::{Candle, Message};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Couldn't connect to {0}: {1}")]
+    Connect(String, io::Error),
+
+    #[error("Error talking to the server: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Can't make sense of '{0}'. Try 'help'.")]
+    InvalidCommand(String),
+
+    #[error("Line {0}: {1}")]
+    Script(usize, Box<ClientError>),
+}
+
+const HELP: &str = "\
+Commands:
+  insert <timestamp> <price>
+  insert-if-absent <timestamp> <price>
+  delete <timestamp>
+  delete-range <mintime> <maxtime>
+  query <mintime> <maxtime>
+  candles <mintime> <maxtime> <width>
+  help
+  quit
+Lines starting with # are ignored.";
+
+// A line of input to the client.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Send(Message),
+    Help,
+    Quit,
+    // A blank line or a comment.
+    Nothing,
+}
+
+// Parses a command like `insert 1000 100` or `query 900 1100`.
+pub fn parse_command(command: &str) -> Result<Command, ClientError> {
+    let invalid = || ClientError::InvalidCommand(command.trim().to_string());
+
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, _)) if name.starts_with('#') => return Ok(Command::Nothing),
+        Some(split) => split,
+        None => return Ok(Command::Nothing),
+    };
+    let args = args
+        .iter()
+        .map(|a| a.parse::<i32>())
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|_| invalid())?;
+
+    let message = match (name.to_lowercase().as_str(), args.as_slice()) {
+        ("help", []) => return Ok(Command::Help),
+        ("quit" | "exit", []) => return Ok(Command::Quit),
+        ("insert", [timestamp, price]) => Message::Insert {
+            timestamp: *timestamp,
+            price: *price,
+        },
+        ("insert-if-absent", [timestamp, price]) => Message::InsertIfAbsent {
+            timestamp: *timestamp,
+            price: *price,
+        },
+        ("delete", [timestamp]) => Message::Delete {
+            timestamp: *timestamp,
+        },
+        ("delete-range", [mintime, maxtime]) => Message::DeleteRange {
+            mintime: *mintime,
+            maxtime: *maxtime,
+        },
+        ("query", [mintime, maxtime]) => Message::Query {
+            mintime: *mintime,
+            maxtime: *maxtime,
+        },
+        ("candles", [mintime, maxtime, width]) => Message::Candles {
+            mintime: *mintime,
+            maxtime: *maxtime,
+            width: *width,
+        },
+        _ => return Err(invalid()),
+    };
+    Ok(Command::Send(message))
+}
+
+// What the server sent back for a message, decoded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Response {
+    Mean(i32),
+    Candles(Vec<Candle>),
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Response::Mean(mean) => write!(f, "{}", mean),
+            Response::Candles(candles) if candles.is_empty() => write!(f, "no candles"),
+            Response::Candles(candles) => {
+                for (i, c) in candles.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(
+                        f,
+                        "start={} open={} high={} low={} close={} mean={} count={}",
+                        c.start, c.open, c.high, c.low, c.close, c.mean, c.count
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// A connection to the server. Everything sent over one goes into the same
+// session.
+pub struct Session {
+    stream: TcpStream,
+}
+
+impl Session {
+    pub fn connect(address: &str) -> Result<Session, ClientError> {
+        let stream = TcpStream::connect(address)
+            .map_err(|e| ClientError::Connect(address.to_string(), e))?;
+        Ok(Session { stream })
+    }
+
+    // Sends `message` and waits for its response, if it gets one.
+    pub fn send(&mut self, message: &Message) -> Result<Option<Response>, ClientError> {
+        self.stream.write_all(&message.to_frame())?;
+        match message {
+            Message::Query { .. } => Ok(Some(Response::Mean(self.read_i32()?))),
+            Message::Candles { .. } => {
+                let count = self.read_i32()?;
+                let candles = (0..count)
+                    .map(|_| {
+                        Ok(Candle {
+                            start: self.read_i32()?,
+                            open: self.read_i32()?,
+                            high: self.read_i32()?,
+                            low: self.read_i32()?,
+                            close: self.read_i32()?,
+                            mean: self.read_i32()?,
+                            count: self.read_i32()?,
+                        })
+                    })
+                    .collect::<io::Result<Vec<Candle>>>()?;
+                Ok(Some(Response::Candles(candles)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn read_i32(&mut self) -> io::Result<i32> {
+        let mut bytes = [0; 4];
+        self.stream.read_exact(&mut bytes)?;
+        Ok(i32::from_be_bytes(bytes))
+    }
+}
+
+// Sends `commands` to the server at `address`, in one session, and writes the
+// responses to `output`.
+pub fn run_client<W: Write>(
+    address: &str,
+    commands: &[String],
+    output: &mut W,
+) -> Result<(), ClientError> {
+    // Check them all before sending any.
+    let commands = commands
+        .iter()
+        .map(|c| parse_command(c))
+        .collect::<Result<Vec<Command>, _>>()?;
+
+    let mut session = Session::connect(address)?;
+    for command in commands {
+        if !run_command(&mut session, command, output)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Reads commands a line at a time from `input`, and writes the responses to
+// `output`, until the input ends or says to quit.
+//
+// Interactively, a prompt is shown before each command and a bad command is
+// reported and skipped. Otherwise `input` is a script, which stops at the
+// first bad command.
+pub fn run_repl<R: BufRead, W: Write>(
+    address: &str,
+    input: R,
+    output: &mut W,
+    interactive: bool,
+) -> Result<(), ClientError> {
+    let mut session = Session::connect(address)?;
+
+    if interactive {
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    for (i, line) in input.lines().enumerate() {
+        let keep_going =
+            parse_command(&line?).and_then(|command| run_command(&mut session, command, output));
+        match keep_going {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e @ ClientError::InvalidCommand(_)) if interactive => writeln!(output, "{}", e)?,
+            Err(e) if interactive => return Err(e),
+            Err(e) => return Err(ClientError::Script(i + 1, Box::new(e))),
+        }
+        if interactive {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// Returns whether to carry on with more commands.
+fn run_command<W: Write>(
+    session: &mut Session,
+    command: Command,
+    output: &mut W,
+) -> Result<bool, ClientError> {
+    match command {
+        Command::Send(message) => {
+            if let Some(response) = session.send(&message)? {
+                writeln!(output, "{}", response)?;
+            }
+        }
+        Command::Help => writeln!(output, "{}", HELP)?,
+        Command::Quit => return Ok(false),
+        Command::Nothing => {}
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_command, Command};
+    use crate::means_to_an_end::Message;
+
+    #[test]
+    fn test_parse_command() {
+        let cases = [
+            (
+                "insert 1000 100",
+                Command::Send(Message::Insert {
+                    timestamp: 1000,
+                    price: 100,
+                }),
+            ),
+            (
+                "  QUERY   900 -1100 ",
+                Command::Send(Message::Query {
+                    mintime: 900,
+                    maxtime: -1100,
+                }),
+            ),
+            (
+                "insert-if-absent 1 2",
+                Command::Send(Message::InsertIfAbsent {
+                    timestamp: 1,
+                    price: 2,
+                }),
+            ),
+            ("delete 5", Command::Send(Message::Delete { timestamp: 5 })),
+            (
+                "delete-range 5 10",
+                Command::Send(Message::DeleteRange {
+                    mintime: 5,
+                    maxtime: 10,
+                }),
+            ),
+            (
+                "candles 0 100 10",
+                Command::Send(Message::Candles {
+                    mintime: 0,
+                    maxtime: 100,
+                    width: 10,
+                }),
+            ),
+            ("help", Command::Help),
+            ("quit", Command::Quit),
+            ("exit", Command::Quit),
+            ("", Command::Nothing),
+            ("   ", Command::Nothing),
+            ("# insert 1 2", Command::Nothing),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(parse_command(command).unwrap(), expected, "{:?}", command);
+        }
+
+        for command in [
+            "insert",
+            "insert 1",
+            "insert 1 2 3",
+            "query a b",
+            "fly 1 2",
+            "help me",
+            "insert 1 99999999999",
+        ] {
+            assert!(parse_command(command).is_err(), "{:?}", command);
+        }
+    }
+}
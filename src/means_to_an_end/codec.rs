@@ -0,0 +1,234 @@
+use super::{Candle, MeansToAnEndError, Message};
+
+pub const FRAME_LEN: usize = 9;
+pub const CANDLES_FRAME_LEN: usize = 13;
+
+// How the decoder deals with a frame whose type byte isn't one we know.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum GarbagePolicy {
+    // Treat the bad frame as a whole 9-byte frame and throw it away.
+    Reject,
+    // Throw away bytes one at a time until the next known message type so
+    // that we can recover from a client that's fallen out of alignment.
+    Resync,
+}
+
+// Turns a stream of bytes, fed in chunks of any size, into messages.
+//
+// Bytes are buffered until there's a whole frame, so a single read from the
+// network can yield many messages and a frame can straddle many reads.
+pub struct MessageDecoder {
+    buf: Vec<u8>,
+    // Index of the first byte in `buf` that hasn't been decoded yet.
+    pos: usize,
+    garbage_policy: GarbagePolicy,
+}
+
+impl MessageDecoder {
+    pub fn new(garbage_policy: GarbagePolicy) -> MessageDecoder {
+        MessageDecoder {
+            buf: vec![],
+            pos: 0,
+            garbage_policy,
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        // Drop everything we've already decoded before growing the buffer.
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(bytes);
+    }
+
+    // Returns the next message, an error for a frame we couldn't make sense
+    // of, or `None` if we need more bytes.
+    pub fn decode(&mut self) -> Option<Result<Message, MeansToAnEndError>> {
+        let pending = &self.buf[self.pos..];
+
+        if self.garbage_policy == GarbagePolicy::Resync && !pending.is_empty() {
+            let skipped = pending
+                .iter()
+                .position(|b| Message::is_type_byte(*b))
+                .unwrap_or(pending.len());
+            if skipped > 0 {
+                let garbage = pending[..skipped].to_vec();
+                self.pos += skipped;
+                return Some(Err(MeansToAnEndError::InvalidMessageType(garbage)));
+            }
+        }
+
+        match pending.first() {
+            Some(b'C') if pending.len() >= CANDLES_FRAME_LEN => {
+                let mut frame = [0; CANDLES_FRAME_LEN];
+                frame.copy_from_slice(&pending[..CANDLES_FRAME_LEN]);
+                self.pos += CANDLES_FRAME_LEN;
+                Some(Ok(Message::candles_from_network_bytes(frame)))
+            }
+            Some(b'C') => None,
+            _ if pending.len() >= FRAME_LEN => {
+                let mut frame = [0; FRAME_LEN];
+                frame.copy_from_slice(&pending[..FRAME_LEN]);
+                self.pos += FRAME_LEN;
+                Some(Message::from_network_bytes(frame))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub const ERROR_FRAME_TYPE: u8 = b'E';
+pub const ERROR_CODE_INVALID_MESSAGE_TYPE: i32 = 1;
+
+pub struct ResponseEncoder;
+
+impl ResponseEncoder {
+    pub fn encode_mean(mean: i32, out: &mut Vec<u8>) {
+        out.extend_from_slice(&mean.to_be_bytes());
+    }
+
+    // A candles response is an i32 count followed by that many candles. Each
+    // candle is seven i32s: start, open, high, low, close, mean and count.
+    pub fn encode_candles(candles: &[Candle], out: &mut Vec<u8>) {
+        out.extend_from_slice(&(candles.len() as i32).to_be_bytes());
+        for candle in candles {
+            for field in [
+                candle.start,
+                candle.open,
+                candle.high,
+                candle.low,
+                candle.close,
+                candle.mean,
+                candle.count,
+            ] {
+                out.extend_from_slice(&field.to_be_bytes());
+            }
+        }
+    }
+
+    // Error frames are laid out like requests: the type byte 'E', then an i32
+    // error code, then an i32 detail. For an invalid message type the detail
+    // is the first offending byte.
+    pub fn encode_error(error: &MeansToAnEndError, out: &mut Vec<u8>) {
+        let (code, detail) = match error {
+            MeansToAnEndError::InvalidMessageType(bytes) => (
+                ERROR_CODE_INVALID_MESSAGE_TYPE,
+                bytes.first().copied().unwrap_or(0) as i32,
+            ),
+            // Nothing else is reported to clients.
+            MeansToAnEndError::LimitExceeded(_) | MeansToAnEndError::DuplicateTimestamp(_) => {
+                return
+            }
+        };
+        out.push(ERROR_FRAME_TYPE);
+        out.extend_from_slice(&code.to_be_bytes());
+        out.extend_from_slice(&detail.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GarbagePolicy, MessageDecoder};
+    use crate::means_to_an_end::Message;
+    use proptest::prelude::*;
+
+    fn message_strategy() -> impl Strategy<Value = Message> {
+        prop_oneof![
+            (any::<i32>(), any::<i32>())
+                .prop_map(|(timestamp, price)| Message::Insert { timestamp, price }),
+            (any::<i32>(), any::<i32>())
+                .prop_map(|(timestamp, price)| Message::InsertIfAbsent { timestamp, price }),
+            any::<i32>().prop_map(|timestamp| Message::Delete { timestamp }),
+            (any::<i32>(), any::<i32>())
+                .prop_map(|(mintime, maxtime)| Message::DeleteRange { mintime, maxtime }),
+            (any::<i32>(), any::<i32>())
+                .prop_map(|(mintime, maxtime)| Message::Query { mintime, maxtime }),
+            (any::<i32>(), any::<i32>(), any::<i32>()).prop_map(|(mintime, maxtime, width)| {
+                Message::Candles {
+                    mintime,
+                    maxtime,
+                    width,
+                }
+            }),
+        ]
+    }
+
+    // Feeds `bytes` to a decoder in chunks of the given sizes, cycling through
+    // them, and collects everything it decodes.
+    fn decode_in_chunks(
+        policy: GarbagePolicy,
+        bytes: &[u8],
+        chunk_sizes: &[usize],
+    ) -> Vec<Option<Message>> {
+        let mut decoder = MessageDecoder::new(policy);
+        let mut decoded = vec![];
+        let mut remaining = bytes;
+        for chunk_size in chunk_sizes.iter().cycle() {
+            if remaining.is_empty() {
+                break;
+            }
+            let (chunk, rest) = remaining.split_at((*chunk_size).min(remaining.len()));
+            remaining = rest;
+            decoder.extend(chunk);
+            while let Some(result) = decoder.decode() {
+                decoded.push(result.ok());
+            }
+        }
+        decoded
+    }
+
+    proptest! {
+        #[test]
+        fn test_decodes_across_arbitrary_chunk_splits(
+            messages in prop::collection::vec(message_strategy(), 0..50),
+            chunk_sizes in prop::collection::vec(1..40usize, 1..10),
+        ) {
+            let bytes: Vec<u8> = messages.iter().flat_map(|m| m.to_frame()).collect();
+
+            for policy in [GarbagePolicy::Reject, GarbagePolicy::Resync] {
+                let decoded = decode_in_chunks(policy, &bytes, &chunk_sizes);
+                let expected: Vec<Option<Message>> = messages.iter().copied().map(Some).collect();
+                prop_assert_eq!(decoded, expected);
+            }
+        }
+
+        #[test]
+        fn test_resyncs_after_garbage(
+            messages in prop::collection::vec(message_strategy(), 1..20),
+            garbage in prop::collection::vec(any::<u8>().prop_filter("not a type byte", |b| !Message::is_type_byte(*b)), 1..20),
+            chunk_sizes in prop::collection::vec(1..40usize, 1..10),
+        ) {
+            let mut bytes = garbage;
+            bytes.extend(messages.iter().flat_map(|m| m.to_frame()));
+
+            let decoded: Vec<Message> = decode_in_chunks(GarbagePolicy::Resync, &bytes, &chunk_sizes)
+                .into_iter()
+                .flatten()
+                .collect();
+            prop_assert_eq!(decoded, messages);
+        }
+    }
+
+    #[test]
+    fn test_rejects_whole_frame_with_unknown_type() {
+        let mut bytes = vec![b'X'; 9];
+        bytes.extend_from_slice(
+            &Message::Query {
+                mintime: 1,
+                maxtime: 2,
+            }
+            .to_frame(),
+        );
+
+        let decoded = decode_in_chunks(GarbagePolicy::Reject, &bytes, &[4]);
+        assert_eq!(
+            decoded,
+            vec![
+                None,
+                Some(Message::Query {
+                    mintime: 1,
+                    maxtime: 2
+                })
+            ]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+// A process-wide set of named counters. Services bump these when something
+// noteworthy happens (e.g. a client hits a limit) so that we can see it
+// without trawling through logs.
+static COUNTERS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+// Counters kept by whatever counts them, for hot paths that can't afford to
+// take the lock above every time. They're read along with the rest, for as
+// long as their owner keeps them.
+static REGISTERED: Mutex<Vec<(&'static str, Weak<AtomicU64>)>> = Mutex::new(Vec::new());
+
+pub fn increment(name: &'static str) {
+    add(name, 1);
+}
+
+pub fn add(name: &'static str, value: u64) {
+    let mut counters = COUNTERS.lock().unwrap();
+    *counters.entry(name).or_insert(0) += value;
+}
+
+// Reports `counter` as `name`, added to any other counters by that name.
+pub fn register(name: &'static str, counter: &Arc<AtomicU64>) {
+    REGISTERED
+        .lock()
+        .unwrap()
+        .push((name, Arc::downgrade(counter)));
+}
+
+// Returns every counter, sorted by name.
+pub fn snapshot() -> Vec<(&'static str, u64)> {
+    let mut counters = COUNTERS.lock().unwrap().clone();
+    let mut registered = REGISTERED.lock().unwrap();
+    registered.retain(|(name, counter)| match counter.upgrade() {
+        Some(counter) => {
+            *counters.entry(name).or_insert(0) += counter.load(Ordering::Relaxed);
+            true
+        }
+        None => false,
+    });
+    counters.into_iter().collect()
+}
@@ -1,82 +1,543 @@
+use crossbeam::channel::{self, Receiver};
 use json::object;
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
+use log::debug;
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use std::collections::HashSet;
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use thiserror::Error;
+use threadpool::ThreadPool;
+
+use crate::line_reader::{LineError, LineReader};
+use crate::Connection;
+use cache::PrimalityCache;
+use document::{Document, JsonDocument};
+use number::Number;
+
+mod cache;
+pub mod client;
+mod document;
+mod json_rpc;
+mod msgpack;
+mod number;
+mod primality;
+mod raw_json;
 
 #[derive(Debug, Error)]
 enum PrimeTimeError {
-    #[error("Invalid JSON request.")]
-    InvalidRequest,
+    #[error("Request is not valid JSON.")]
+    InvalidJson,
+
+    #[error("Request has no method.")]
+    MissingMethod,
+
+    #[error("Unknown method '{0}'.")]
+    UnknownMethod(String),
+
+    #[error("Request has no '{0}'.")]
+    MissingArgument(&'static str),
+
+    #[error("'{0}' must be a number.")]
+    NonNumericArgument(&'static str),
+
+    #[error("'{0}' is not a value this method accepts.")]
+    InvalidArgument(&'static str),
+
+    #[error("Request is longer than {0} bytes.")]
+    OversizedLine(usize),
+
+    #[error("Unexpected field '{0}'.")]
+    UnknownField(String),
+
+    #[error("Field '{0}' appears more than once.")]
+    DuplicateField(String),
+
+    #[error("Request is not valid MessagePack, or holds something JSON couldn't.")]
+    InvalidMessagePack,
+}
+
+impl PrimeTimeError {
+    // A short, stable name for the error for clients to match on.
+    fn kind(&self) -> &'static str {
+        match self {
+            PrimeTimeError::InvalidJson => "invalidJson",
+            PrimeTimeError::MissingMethod => "missingMethod",
+            PrimeTimeError::UnknownMethod(_) => "unknownMethod",
+            PrimeTimeError::MissingArgument(_) => "missingArgument",
+            PrimeTimeError::NonNumericArgument(_) => "nonNumericArgument",
+            PrimeTimeError::InvalidArgument(_) => "invalidArgument",
+            PrimeTimeError::OversizedLine(_) => "oversizedLine",
+            PrimeTimeError::UnknownField(_) => "unknownField",
+            PrimeTimeError::DuplicateField(_) => "duplicateField",
+            PrimeTimeError::InvalidMessagePack => "invalidMessagePack",
+        }
+    }
+}
+
+// How requests and responses are framed on the wire. Either way, it's one
+// JSON document per line.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum Protocol {
+    // The protocol from the Protohackers spec: a malformed request gets
+    // `ERROR` and the connection is closed.
+    Protohackers,
+    // JSON-RPC 2.0, with the method's arguments as named params.
+    JsonRpc,
+}
+
+// How fussy to be about the fields in a request.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum)]
+pub enum Validation {
+    // Ignore fields we don't use, and let the last of any duplicates win.
+    Lenient,
+    // Reject requests with fields we don't use or with duplicate fields.
+    Strict,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub protocol: Protocol,
+    pub validation: Validation,
+    // Answer malformed requests with a JSON object describing the problem
+    // instead of the bare `ERROR` the spec asks for.
+    pub verbose_errors: bool,
+    // Longest request line we'll read, in bytes, not counting the newline.
+    pub max_line_length: usize,
+    // Threads evaluating requests, shared by every connection.
+    pub compute_threads: usize,
+    // How many requests a single connection can have being evaluated (or
+    // waiting to be written back) before we stop reading from it.
+    pub max_in_flight: usize,
+    // How many primality results to remember across connections.
+    pub cache_capacity: usize,
+    // Numbers up to this are looked up in a sieve built at startup.
+    pub sieve_limit: usize,
+    // Also accept requests in MessagePack frames from clients whose first
+    // byte says they're using them. See `msgpack`.
+    pub binary_transport: bool,
+    // Longest number, in digits, we'll run a primality test on. Longer ones
+    // are still answered when a small factor gives them away.
+    pub max_digits: u32,
+}
+
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 1024 * 1024;
+pub const DEFAULT_COMPUTE_THREADS: usize = 4;
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+pub const DEFAULT_CACHE_CAPACITY: usize = 100_000;
+pub const DEFAULT_SIEVE_LIMIT: usize = 10_000_000;
+// Testing takes time that grows with about the cube of a number's length: a
+// thousand digits take milliseconds, but a line's worth would tie up a worker
+// for hours.
+pub const DEFAULT_MAX_DIGITS: u32 = 1000;
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            protocol: Protocol::Protohackers,
+            validation: Validation::Lenient,
+            verbose_errors: false,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            compute_threads: DEFAULT_COMPUTE_THREADS,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            sieve_limit: DEFAULT_SIEVE_LIMIT,
+            binary_transport: false,
+            max_digits: DEFAULT_MAX_DIGITS,
+        }
+    }
+}
+
+type Response = Result<Option<json::JsonValue>, PrimeTimeError>;
+
+// A request as it came off the wire, in either transport.
+enum Request {
+    Line(String),
+    Frame(Vec<u8>),
 }
 
-pub fn handle_connection(mut stream: TcpStream) {
-    let mut read_stream = stream.try_clone().unwrap();
-    let mut reader = BufReader::new(&mut read_stream);
+impl Request {
+    // How many bytes it took on the wire.
+    fn wire_len(&self) -> usize {
+        match self {
+            Request::Line(line) => line.len() + 1,
+            Request::Frame(frame) => frame.len() + 4,
+        }
+    }
+}
 
-    loop {
-        let mut buf = String::new();
+enum RequestReader {
+    Lines(LineReader<TcpStream>),
+    Frames(BufReader<TcpStream>),
+}
 
-        match reader.read_line(&mut buf) {
-            Ok(0) => {
-                // EOF -- connection closed. No-op.
-                break;
+impl RequestReader {
+    // Returns the next request, or `None` at EOF.
+    fn read_request(&mut self, max_len: usize) -> Result<Option<Request>, PrimeTimeError> {
+        match self {
+            RequestReader::Lines(reader) => match reader.read_line() {
+                Ok(line) => Ok(line.map(Request::Line)),
+                Err(LineError::TooLong(max)) => Err(PrimeTimeError::OversizedLine(max)),
+                // An error occurred reading from the stream.
+                Err(_e) => Err(PrimeTimeError::InvalidJson),
+            },
+            RequestReader::Frames(reader) => {
+                Ok(msgpack::read_frame(reader, max_len)?.map(Request::Frame))
             }
-            Ok(_) => match json::parse(&buf.clone()) {
-                // We read a line.
-                Ok(p) => match validate_request(p) {
-                    Ok(r) => {
-                        let mut response_string = r.dump();
-                        response_string.push('\n');
-                        stream.write_all(response_string.as_bytes()).unwrap();
+        }
+    }
+}
+
+pub struct Server {
+    config: Config,
+    compute_pool: Mutex<ThreadPool>,
+    cache: Arc<PrimalityCache>,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Server {
+        let compute_pool = Mutex::new(ThreadPool::new(config.compute_threads));
+        let cache = Arc::new(PrimalityCache::new(
+            config.cache_capacity,
+            config.sieve_limit,
+        ));
+        Server {
+            config,
+            compute_pool,
+            cache,
+        }
+    }
+
+    // Requests on a connection are pipelined: we keep reading lines while
+    // earlier ones are evaluated on the compute pool, and a writer thread sends
+    // responses back in the order the requests came in.
+    pub fn handle_connection(&self, stream: TcpStream, connection: Connection) {
+        let binary = self.config.binary_transport && starts_with_frame(&stream);
+        let read_stream = stream.try_clone().unwrap();
+        let mut reader = if binary {
+            RequestReader::Frames(BufReader::new(read_stream))
+        } else {
+            RequestReader::Lines(LineReader::new(read_stream, self.config.max_line_length))
+        };
+
+        // Each in-flight request gets a slot its response will arrive on. The
+        // queue of slots being bounded is what limits requests in flight.
+        let (slots_tx, slots_rx) =
+            channel::bounded::<Receiver<Response>>(self.config.max_in_flight);
+
+        thread::scope(|scope| {
+            scope.spawn(|| self.write_responses(stream, &connection, slots_rx, binary));
+
+            loop {
+                let request = match reader.read_request(self.config.max_line_length) {
+                    Ok(None) => {
+                        // EOF -- connection closed. No-op.
+                        break;
+                    }
+                    Ok(Some(request)) => {
+                        connection.record_received(request.wire_len());
+                        connection.wait_for_message();
+                        Ok(request)
+                    }
+                    Err(e) => Err(e),
+                };
+
+                let (response_tx, response_rx) = channel::bounded(1);
+                if slots_tx.send(response_rx).is_err() {
+                    // The writer's given up on this connection.
+                    break;
+                }
+
+                match request {
+                    Ok(request) => {
+                        // We read a request.
+                        let config = self.config.clone();
+                        let cache = self.cache.clone();
+                        self.compute_pool.lock().unwrap().execute(move || {
+                            let response = match request {
+                                Request::Line(line) => evaluate(&line, &cache, &config),
+                                Request::Frame(frame) => msgpack::decode(&frame)
+                                    .and_then(|request| respond(&request, &cache, &config)),
+                            };
+                            let _ = response_tx.send(response);
+                        });
                     }
-                    Err(_e) => {
-                        write_malformed_response(&mut stream);
+                    Err(e) => {
+                        let _ = response_tx.send(Err(e));
                         break;
                     }
-                }, //
-                Err(_e) => {
-                    write_malformed_response(&mut stream);
+                }
+            }
+
+            // Let the writer finish up with whatever's still in flight.
+            drop(slots_tx);
+        });
+    }
+
+    fn write_responses(
+        &self,
+        mut stream: TcpStream,
+        connection: &Connection,
+        slots: Receiver<Receiver<Response>>,
+        binary: bool,
+    ) {
+        let mut answered = 0;
+        for slot in slots {
+            let response = match slot.recv() {
+                Ok(response) => response,
+                // The evaluation panicked.
+                Err(_) => Err(PrimeTimeError::InvalidJson),
+            };
+
+            let result = match response {
+                Ok(Some(r)) if binary => msgpack::write_frame(&mut stream, &r),
+                Ok(Some(r)) => write_line(&mut stream, r.dump()),
+                Ok(None) => Ok(0),
+                Err(e) => {
+                    debug!("Malformed request from {}: {}", connection.client, e);
+                    if let Ok(sent) = self.write_malformed_response(&mut stream, &e, binary) {
+                        connection.record_sent(sent);
+                    }
+                    // Stop the reader too.
+                    let _ = stream.shutdown(Shutdown::Both);
+                    break;
+                }
+            };
+            match result {
+                Ok(sent) => {
+                    connection.record_sent(sent);
+                    answered += 1;
+                    connection.set_state(format!("{} requests answered", answered));
+                }
+                Err(_) => {
+                    let _ = stream.shutdown(Shutdown::Both);
                     break;
                 }
-            },
-            Err(_e) => {
-                // An error occurred reading from the stream.
-                write_malformed_response(&mut stream);
-                break;
             }
         }
     }
+
+    fn write_malformed_response(
+        &self,
+        stream: &mut TcpStream,
+        error: &PrimeTimeError,
+        binary: bool,
+    ) -> io::Result<usize> {
+        if binary {
+            // Binary clients always get the details.
+            let response = match self.config.protocol {
+                Protocol::JsonRpc => json_rpc::respond_to_error(error),
+                Protocol::Protohackers => object! {
+                    error: error.kind(),
+                    message: error.to_string(),
+                },
+            };
+            msgpack::write_frame(stream, &response)
+        } else if self.config.protocol == Protocol::JsonRpc {
+            write_line(stream, json_rpc::respond_to_error(error).dump())
+        } else if self.config.verbose_errors {
+            let response = object! {
+                error: error.kind(),
+                message: error.to_string(),
+            };
+            write_line(stream, response.dump())
+        } else {
+            stream.write_all(b"ERROR")?;
+            Ok(5)
+        }
+    }
 }
 
-fn validate_request(obj: json::JsonValue) -> Result<json::JsonValue, PrimeTimeError> {
-    if obj.has_key("method")
-        && obj.has_key("number")
-        && !obj["method"].is_null()
-        && obj["method"] == "isPrime"
-        && !obj["number"].is_null()
-    {
-        match obj["number"] {
-            json::JsonValue::Number(n) => {
-                if n.is_nan() {
-                    Err(PrimeTimeError::InvalidRequest)
-                } else {
-                    // We have a valid number. It might be floating point or negative.
-                    let (positive, _mantissa, exponent) = n.as_parts();
-                    let is_prime = exponent == 0
-                        && positive
-                        && primal::is_prime(n.as_fixed_point_u64(0).unwrap());
-
-                    let response_obj = object! {method: "isPrime", prime: is_prime};
-                    Ok(response_obj)
-                }
+// Writes `line` and a newline, returning how many bytes that took.
+fn write_line(stream: &mut TcpStream, mut line: String) -> io::Result<usize> {
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(line.len())
+}
+
+// Waits for the client's first byte to see which transport it's using.
+fn starts_with_frame(stream: &TcpStream) -> bool {
+    let mut first = [0; 1];
+    matches!(stream.peek(&mut first), Ok(1) if first[0] == 0)
+}
+
+// Responds to a request line in the configured protocol.
+fn evaluate(line: &str, cache: &PrimalityCache, config: &Config) -> Response {
+    match json::parse(line) {
+        Ok(request) => respond(JsonDocument::new(&request, line), cache, config),
+        // JSON-RPC has its own way of saying so.
+        Err(_) if config.protocol == Protocol::JsonRpc => Ok(Some(json_rpc::respond_to_error(
+            &PrimeTimeError::InvalidJson,
+        ))),
+        Err(_) => Err(PrimeTimeError::InvalidJson),
+    }
+}
+
+// Responds to a request, from either transport, in the configured protocol.
+fn respond<D: Document>(request: D, cache: &PrimalityCache, config: &Config) -> Response {
+    match config.protocol {
+        Protocol::Protohackers => respond_to_request(request, cache, config).map(Some),
+        Protocol::JsonRpc => Ok(json_rpc::respond(request, cache, config)),
+    }
+}
+
+// Responds to a request in the Protohackers protocol.
+fn respond_to_request<D: Document>(
+    request: D,
+    cache: &PrimalityCache,
+    config: &Config,
+) -> Result<json::JsonValue, PrimeTimeError> {
+    let method = request.get("method").ok_or(PrimeTimeError::MissingMethod)?;
+    let method = method.as_str().ok_or(PrimeTimeError::MissingMethod)?;
+    if config.validation == Validation::Strict {
+        // Leave unknown methods for `validate_request` to complain about.
+        if let Some(argument_names) = argument_names(method) {
+            let mut allowed = vec!["method"];
+            allowed.extend(argument_names);
+            check_fields(&request, &allowed)?;
+        }
+    }
+    validate_request(method, &request, cache, config)
+}
+
+// The arguments each method takes, or `None` if there's no such method.
+fn argument_names(method: &str) -> Option<&'static [&'static str]> {
+    match method {
+        "isPrime" | "factorize" | "nextPrime" | "primeCount" | "nthPrime" => Some(&["number"]),
+        "isProbablePrime" => Some(&["number", "confidence"]),
+        _ => None,
+    }
+}
+
+// Checks that `object` has only `allowed` fields, each at most once.
+fn check_fields<D: Document>(object: &D, allowed: &[&str]) -> Result<(), PrimeTimeError> {
+    let mut seen = HashSet::new();
+    for key in object.keys().unwrap_or_default() {
+        if !allowed.contains(&key.as_str()) {
+            return Err(PrimeTimeError::UnknownField(key));
+        }
+        if !seen.insert(key.clone()) {
+            return Err(PrimeTimeError::DuplicateField(key));
+        }
+    }
+    Ok(())
+}
+
+// Arguments above these are refused rather than tying up a worker sieving.
+const MAX_PRIME_COUNT_NUMBER: u64 = 1_000_000_000;
+const MAX_NTH_PRIME_NUMBER: u64 = 50_000_000;
+
+const DEFAULT_CONFIDENCE: u32 = 20;
+const MAX_CONFIDENCE: u32 = 128;
+
+// Runs `method` with the arguments in the object `args`.
+fn validate_request<D: Document>(
+    method: &str,
+    args: &D,
+    cache: &PrimalityCache,
+    config: &Config,
+) -> Result<json::JsonValue, PrimeTimeError> {
+    match method {
+        "isPrime" => is_prime(args, cache, config.max_digits),
+        "factorize" => {
+            let n = u64_argument(args, "number")?;
+            let factors: Vec<json::JsonValue> = primality::factorize(n)
+                .into_iter()
+                .map(|f| f.into())
+                .collect();
+            Ok(object! {method: "factorize", factors: factors})
+        }
+        "nextPrime" => {
+            let n = u64_argument(args, "number")?;
+            let prime =
+                primality::next_prime(n).ok_or(PrimeTimeError::InvalidArgument("number"))?;
+            Ok(object! {method: "nextPrime", prime: prime})
+        }
+        "primeCount" => {
+            let n = u64_argument(args, "number")?;
+            if n > MAX_PRIME_COUNT_NUMBER {
+                return Err(PrimeTimeError::InvalidArgument("number"));
+            }
+            let count = primal::StreamingSieve::prime_pi(n as usize);
+            Ok(object! {method: "primeCount", count: count})
+        }
+        "nthPrime" => {
+            let n = u64_argument(args, "number")?;
+            if n == 0 || n > MAX_NTH_PRIME_NUMBER {
+                return Err(PrimeTimeError::InvalidArgument("number"));
             }
-            _ => Err(PrimeTimeError::InvalidRequest),
+            let prime = primal::StreamingSieve::nth_prime(n as usize);
+            Ok(object! {method: "nthPrime", prime: prime})
         }
-    } else {
-        Err(PrimeTimeError::InvalidRequest)
+        "isProbablePrime" => {
+            let n = integer_argument(args, "number")?;
+            let confidence = if args.get("confidence").is_some() {
+                u64_argument(args, "confidence")?
+            } else {
+                DEFAULT_CONFIDENCE as u64
+            };
+            if confidence == 0 || confidence > MAX_CONFIDENCE as u64 {
+                return Err(PrimeTimeError::InvalidArgument("confidence"));
+            }
+            let prime = if !primality::has_more_digits_than(&n, config.max_digits) {
+                primality::is_probable_prime(&n, confidence as u32)
+            } else if primality::has_small_factor(&n) {
+                false
+            } else {
+                return Err(PrimeTimeError::InvalidArgument("number"));
+            };
+            Ok(object! {method: "isProbablePrime", prime: prime})
+        }
+        _ => Err(PrimeTimeError::UnknownMethod(method.to_string())),
+    }
+}
+
+fn is_prime<D: Document>(
+    args: &D,
+    cache: &PrimalityCache,
+    max_digits: u32,
+) -> Result<json::JsonValue, PrimeTimeError> {
+    // Negative numbers, fractions and anything else that isn't a positive
+    // integer is a valid request, it just isn't prime. Positive integers too
+    // long to test are refused, unless a small factor shows they aren't prime.
+    let is_prime = match number_argument(args, "number")? {
+        Number::Integer(n) => match n.to_biguint() {
+            Some(n) if !primality::has_more_digits_than(&n, max_digits) => cache.is_prime(&n),
+            Some(n) if primality::has_small_factor(&n) => false,
+            Some(_) => return Err(PrimeTimeError::InvalidArgument("number")),
+            None => false,
+        },
+        // These are multiples of ten.
+        Number::HugeInteger => false,
+        Number::Fraction => false,
+    };
+    Ok(object! {method: "isPrime", prime: is_prime})
+}
+
+// Reads the number `key` from `args`. See `number` for what the number means.
+fn number_argument<D: Document>(args: &D, key: &'static str) -> Result<Number, PrimeTimeError> {
+    let value = args
+        .get(key)
+        .filter(|v| !v.is_null())
+        .ok_or(PrimeTimeError::MissingArgument(key))?;
+    value
+        .as_number()
+        .ok_or(PrimeTimeError::NonNumericArgument(key))
+}
+
+// The methods other than isPrime only accept non-negative integers, though
+// they can be written in any form that `number` counts as an integer.
+fn integer_argument<D: Document>(args: &D, key: &'static str) -> Result<BigUint, PrimeTimeError> {
+    match number_argument(args, key)? {
+        Number::Integer(n) => n.to_biguint().ok_or(PrimeTimeError::InvalidArgument(key)),
+        Number::HugeInteger | Number::Fraction => Err(PrimeTimeError::InvalidArgument(key)),
     }
 }
 
-fn write_malformed_response(stream: &mut TcpStream) {
-    stream.write_all(b"ERROR").unwrap();
+fn u64_argument<D: Document>(args: &D, key: &'static str) -> Result<u64, PrimeTimeError> {
+    integer_argument(args, key)?
+        .to_u64()
+        .ok_or(PrimeTimeError::InvalidArgument(key))
 }
@@ -0,0 +1,201 @@
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::primality;
+use crate::metrics;
+
+// Connections lock one shard at a time so they don't all queue on one lock.
+const NUM_SHARDS: usize = 16;
+
+// Small caches get fewer shards, so each shard's still worth having.
+const MIN_SHARD_CAPACITY: usize = 64;
+
+// Remembers primality results across every connection on a server. Numbers up
+// to `sieve_limit` are answered from a sieve built up front, and bigger ones
+// are kept in a bounded LRU cache once we've worked them out.
+pub struct PrimalityCache {
+    sieve: Option<primal::Sieve>,
+    sieve_limit: usize,
+    shards: Vec<Mutex<Lru>>,
+    // Counted here rather than with `metrics::increment`, so that lookups
+    // only ever take their own shard's lock.
+    sieve_hits: Arc<AtomicU64>,
+    cache_hits: Arc<AtomicU64>,
+    cache_misses: Arc<AtomicU64>,
+}
+
+impl PrimalityCache {
+    // A `capacity` of 0 turns off the LRU cache, and a `sieve_limit` of 0
+    // turns off the sieve.
+    pub fn new(capacity: usize, sieve_limit: usize) -> PrimalityCache {
+        // Split the capacity so the shards add up to exactly `capacity`.
+        let num_shards = (capacity / MIN_SHARD_CAPACITY).clamp(1, NUM_SHARDS);
+        let (shard_capacity, remainder) = (capacity / num_shards, capacity % num_shards);
+        let counter = |name| {
+            let counter = Arc::new(AtomicU64::new(0));
+            metrics::register(name, &counter);
+            counter
+        };
+        PrimalityCache {
+            sieve: (sieve_limit > 0).then(|| primal::Sieve::new(sieve_limit)),
+            sieve_limit,
+            shards: (0..num_shards)
+                .map(|i| Mutex::new(Lru::new(shard_capacity + usize::from(i < remainder))))
+                .collect(),
+            sieve_hits: counter("prime_time.sieve_hits"),
+            cache_hits: counter("prime_time.cache_hits"),
+            cache_misses: counter("prime_time.cache_misses"),
+        }
+    }
+
+    pub fn is_prime(&self, n: &BigUint) -> bool {
+        if let (Some(sieve), Some(small_n)) = (&self.sieve, n.to_usize()) {
+            if small_n <= self.sieve_limit {
+                self.sieve_hits.fetch_add(1, Ordering::Relaxed);
+                return sieve.is_prime(small_n);
+            }
+        }
+
+        let shard = &self.shards[shard_index(n, self.shards.len())];
+        if let Some(is_prime) = shard.lock().unwrap().get(n) {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return is_prime;
+        }
+
+        // Work it out without holding the lock: this can take a while.
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        let is_prime = primality::is_prime(n);
+        shard.lock().unwrap().insert(n.clone(), is_prime);
+        is_prime
+    }
+}
+
+fn shard_index(n: &BigUint, num_shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    n.hash(&mut hasher);
+    hasher.finish() as usize % num_shards
+}
+
+// A least-recently-used map from numbers to whether they're prime.
+struct Lru {
+    capacity: usize,
+    // Each entry's value and when it was last used.
+    entries: HashMap<BigUint, (bool, u64)>,
+    // Entries by when they were last used, oldest first.
+    by_last_use: BTreeMap<u64, BigUint>,
+    clock: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Lru {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            by_last_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, n: &BigUint) -> Option<bool> {
+        let (is_prime, last_use) = self.entries.get_mut(n)?;
+        self.clock += 1;
+        let n = self.by_last_use.remove(last_use).unwrap();
+        *last_use = self.clock;
+        self.by_last_use.insert(self.clock, n);
+        Some(*is_prime)
+    }
+
+    fn insert(&mut self, n: BigUint, is_prime: bool) {
+        if self.capacity == 0 || self.get(&n).is_some() {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity {
+            let oldest = *self.by_last_use.keys().next().unwrap();
+            let evicted = self.by_last_use.remove(&oldest).unwrap();
+            self.entries.remove(&evicted);
+        }
+
+        self.clock += 1;
+        self.entries.insert(n.clone(), (is_prime, self.clock));
+        self.by_last_use.insert(self.clock, n);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Lru, PrimalityCache};
+    use num_bigint::BigUint;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.insert(BigUint::from(1u32), false);
+        lru.insert(BigUint::from(2u32), true);
+
+        // Using 1 makes 2 the least recently used.
+        assert_eq!(lru.get(&BigUint::from(1u32)), Some(false));
+        lru.insert(BigUint::from(3u32), true);
+
+        assert_eq!(lru.get(&BigUint::from(1u32)), Some(false));
+        assert_eq!(lru.get(&BigUint::from(2u32)), None);
+        assert_eq!(lru.get(&BigUint::from(3u32)), Some(true));
+        assert_eq!(lru.entries.len(), 2);
+        assert_eq!(lru.by_last_use.len(), 2);
+    }
+
+    #[test]
+    fn test_lru_with_no_capacity() {
+        let mut lru = Lru::new(0);
+        lru.insert(BigUint::from(1u32), false);
+        assert_eq!(lru.get(&BigUint::from(1u32)), None);
+    }
+
+    #[test]
+    fn test_cache_agrees_with_primality() {
+        let cache = PrimalityCache::new(100, 1000);
+        for n in (0..5000u32).chain(0..5000) {
+            assert_eq!(
+                cache.is_prime(&BigUint::from(n)),
+                primal::is_prime(n as u64),
+                "{}",
+                n
+            );
+        }
+
+        // 0 to 1000, twice over.
+        assert_eq!(cache.sieve_hits.load(Ordering::Relaxed), 2002);
+
+        let mersenne: BigUint = "170141183460469231731687303715884105727".parse().unwrap();
+        let (hits, misses) = (
+            cache.cache_hits.load(Ordering::Relaxed),
+            cache.cache_misses.load(Ordering::Relaxed),
+        );
+        assert!(cache.is_prime(&mersenne));
+        assert!(cache.is_prime(&mersenne));
+        assert_eq!(cache.cache_hits.load(Ordering::Relaxed), hits + 1);
+        assert_eq!(cache.cache_misses.load(Ordering::Relaxed), misses + 1);
+    }
+
+    #[test]
+    fn test_cache_holds_no_more_than_capacity() {
+        for capacity in [0, 1, 4, 63, 64, 100, 1000, 1025] {
+            let cache = PrimalityCache::new(capacity, 0);
+            for n in 0..5000u32 {
+                cache.is_prime(&BigUint::from(n));
+            }
+            let held: usize = cache
+                .shards
+                .iter()
+                .map(|s| s.lock().unwrap().entries.len())
+                .sum();
+            assert_eq!(held, capacity);
+        }
+    }
+}
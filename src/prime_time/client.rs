@@ -0,0 +1,100 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Couldn't connect to {0}: {1}")]
+    Connect(String, io::Error),
+
+    #[error("Error talking to the server: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Server rejected a request: {0}")]
+    Rejected(String),
+}
+
+// The request line asking `method` about `number`. `number` goes in as it's
+// written, so it can be as big as you like.
+pub fn request_line(method: &str, number: &str) -> String {
+    format!(
+        "{{\"method\":{},\"number\":{}}}",
+        json::stringify(method),
+        number
+    )
+}
+
+// Sends each of `requests`, a line each, to the server at `address` and
+// writes the responses to `output` as they come back. Requests are pipelined:
+// we don't wait for one response before sending the next request.
+pub fn run_client<I, W>(address: &str, requests: I, output: &mut W) -> Result<(), ClientError>
+where
+    I: Iterator<Item = io::Result<String>> + Send,
+    W: Write,
+{
+    let stream =
+        TcpStream::connect(address).map_err(|e| ClientError::Connect(address.to_string(), e))?;
+
+    thread::scope(|scope| {
+        let writer = scope.spawn(|| -> io::Result<()> {
+            let mut stream = &stream;
+            for request in requests {
+                let mut request = request?;
+                request.push('\n');
+                stream.write_all(request.as_bytes())?;
+            }
+            stream.shutdown(Shutdown::Write)
+        });
+
+        let result = read_responses(&stream, output);
+        if result.is_err() {
+            // Stop the writer if it's still going.
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        let written = writer.join().unwrap();
+        result?;
+        written?;
+        Ok(())
+    })
+}
+
+fn read_responses<W: Write>(stream: &TcpStream, output: &mut W) -> Result<(), ClientError> {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        output.write_all(line.as_bytes())?;
+        output.flush()?;
+
+        // Any response that isn't JSON is the server giving up on us, e.g. the
+        // `ERROR` the Protohackers protocol sends.
+        let response = line.trim_end();
+        if json::parse(response).is_err() {
+            if !line.ends_with('\n') {
+                output.write_all(b"\n")?;
+            }
+            return Err(ClientError::Rejected(response.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::request_line;
+
+    #[test]
+    fn test_request_line() {
+        assert_eq!(
+            request_line("isPrime", "170141183460469231731687303715884105727"),
+            r#"{"method":"isPrime","number":170141183460469231731687303715884105727}"#
+        );
+        assert_eq!(
+            request_line("say \"hi\"", "1"),
+            r#"{"method":"say \"hi\"","number":1}"#
+        );
+    }
+}
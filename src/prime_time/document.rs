@@ -0,0 +1,110 @@
+// A request, or part of one, as it came in over either transport. Methods and
+// JSON-RPC read requests through this, so a MessagePack request is answered
+// straight from what was decoded rather than being turned into JSON text and
+// parsed all over again.
+
+use super::number::{self, Number};
+use super::raw_json;
+
+pub trait Document: Sized {
+    // Stands in for a part of the request that wasn't sent.
+    fn null() -> Self;
+
+    // The value for `key` if this is an object with that key. If the key
+    // appears more than once the last one wins.
+    fn get(&self, key: &str) -> Option<Self>;
+
+    // The keys of an object, in the order they were sent, or `None` if this
+    // isn't an object.
+    fn keys(&self) -> Option<Vec<String>>;
+
+    // The elements of an array, or `None` if this isn't an array.
+    fn elements(&self) -> Option<Vec<Self>>;
+
+    fn is_null(&self) -> bool;
+
+    fn as_str(&self) -> Option<&str>;
+
+    // What the number this holds means, or `None` if it isn't a number. See
+    // `number`.
+    fn as_number(&self) -> Option<Number>;
+
+    // For echoing parts of a request back in a response.
+    fn to_json(&self) -> json::JsonValue;
+}
+
+static NULL: json::JsonValue = json::JsonValue::Null;
+
+// A parsed JSON value alongside the raw text it was parsed from, which is
+// what we read numbers from.
+#[derive(Clone, Copy)]
+pub struct JsonDocument<'a> {
+    value: &'a json::JsonValue,
+    text: &'a str,
+}
+
+impl<'a> JsonDocument<'a> {
+    // `text` must be what `value` was parsed from.
+    pub fn new(value: &'a json::JsonValue, text: &'a str) -> JsonDocument<'a> {
+        JsonDocument {
+            value,
+            text: text.trim(),
+        }
+    }
+}
+
+impl Document for JsonDocument<'_> {
+    fn null() -> Self {
+        JsonDocument {
+            value: &NULL,
+            text: "null",
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Self> {
+        if !self.value.is_object() {
+            return None;
+        }
+        // `json` also lets the last of any duplicate keys win.
+        let text = raw_json::member(self.text, key)?;
+        Some(JsonDocument {
+            value: &self.value[key],
+            text,
+        })
+    }
+
+    fn keys(&self) -> Option<Vec<String>> {
+        let members = raw_json::object_members(self.text)?;
+        Some(members.into_iter().map(|(key, _)| key).collect())
+    }
+
+    fn elements(&self) -> Option<Vec<Self>> {
+        let texts = raw_json::array_elements(self.text)?;
+        Some(
+            self.value
+                .members()
+                .zip(texts)
+                .map(|(value, text)| JsonDocument { value, text })
+                .collect(),
+        )
+    }
+
+    fn is_null(&self) -> bool {
+        self.value.is_null()
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        self.value.as_str()
+    }
+
+    fn as_number(&self) -> Option<Number> {
+        if !self.value.is_number() {
+            return None;
+        }
+        number::parse(self.text)
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        self.value.clone()
+    }
+}
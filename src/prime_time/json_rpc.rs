@@ -0,0 +1,124 @@
+use json::object;
+
+use super::document::Document;
+use super::{
+    argument_names, check_fields, validate_request, Config, PrimalityCache, PrimeTimeError,
+    Validation,
+};
+
+// Error codes from the JSON-RPC 2.0 spec.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+// The fields a call may have.
+const CALL_FIELDS: &[&str] = &["jsonrpc", "method", "params", "id"];
+
+// Responds to a request holding a single call or a batch of calls. Returns
+// `None` when there's nothing to send back, i.e. the request held only
+// notifications.
+pub fn respond<D: Document>(
+    request: D,
+    cache: &PrimalityCache,
+    config: &Config,
+) -> Option<json::JsonValue> {
+    let calls = match request.elements() {
+        Some(calls) => calls,
+        None => return respond_to_call(&request, cache, config),
+    };
+    if calls.is_empty() {
+        return Some(error_response(
+            json::Null,
+            INVALID_REQUEST,
+            "Invalid Request",
+        ));
+    }
+
+    let responses: Vec<json::JsonValue> = calls
+        .iter()
+        .filter_map(|call| respond_to_call(call, cache, config))
+        .collect();
+    if responses.is_empty() {
+        None
+    } else {
+        Some(json::JsonValue::Array(responses))
+    }
+}
+
+fn respond_to_call<D: Document>(
+    call: &D,
+    cache: &PrimalityCache,
+    config: &Config,
+) -> Option<json::JsonValue> {
+    let strict = config.validation == Validation::Strict;
+    let id = call.get("id");
+    let valid_id = match &id {
+        Some(id) => id.is_null() || id.as_str().is_some() || id.as_number().is_some(),
+        None => true,
+    };
+    let params = call.get("params");
+    let valid_params = match &params {
+        Some(params) => params.is_null() || params.keys().is_some() || params.elements().is_some(),
+        None => true,
+    };
+    let method = call.get("method");
+    let method = method.as_ref().and_then(|m| m.as_str());
+
+    if call.keys().is_none()
+        || call.get("jsonrpc").as_ref().and_then(|v| v.as_str()) != Some("2.0")
+        || method.is_none()
+        || !valid_id
+        || !valid_params
+        || (strict && check_fields(call, CALL_FIELDS).is_err())
+    {
+        let id = match &id {
+            Some(id) if valid_id => id.to_json(),
+            _ => json::Null,
+        };
+        return Some(error_response(id, INVALID_REQUEST, "Invalid Request"));
+    }
+
+    // Arguments are named, so positional params are never right.
+    let params = params.unwrap_or_else(D::null);
+    let result = if params.elements().is_some() {
+        Err(PrimeTimeError::InvalidArgument("params"))
+    } else {
+        let method = method.unwrap();
+        match argument_names(method) {
+            Some(argument_names) if strict => check_fields(&params, argument_names),
+            _ => Ok(()),
+        }
+        .and_then(|_| validate_request(method, &params, cache, config))
+    };
+
+    // Calls without an id are notifications, which never get a response.
+    let id = match id {
+        Some(id) => id.to_json(),
+        None => return None,
+    };
+
+    Some(match result {
+        Ok(result) => object! {jsonrpc: "2.0", result: result, id: id},
+        Err(PrimeTimeError::UnknownMethod(_)) => {
+            error_response(id, METHOD_NOT_FOUND, "Method not found")
+        }
+        Err(_) => error_response(id, INVALID_PARAMS, "Invalid params"),
+    })
+}
+
+// Responds to a line we couldn't even read as a request.
+pub fn respond_to_error(error: &PrimeTimeError) -> json::JsonValue {
+    match error {
+        PrimeTimeError::InvalidJson => error_response(json::Null, PARSE_ERROR, "Parse error"),
+        _ => error_response(json::Null, INVALID_REQUEST, "Invalid Request"),
+    }
+}
+
+fn error_response(id: json::JsonValue, code: i32, message: &str) -> json::JsonValue {
+    object! {
+        jsonrpc: "2.0",
+        error: {code: code, message: message},
+        id: id,
+    }
+}
@@ -0,0 +1,298 @@
+// A binary transport for clients that would rather not pay for JSON line
+// parsing. Each request and response is a frame: a 4-byte big-endian length
+// and then that many bytes of MessagePack, holding the same request or
+// response that would otherwise be a JSON line, e.g.
+// `{"method": "isPrime", "number": 97}`.
+//
+// Frames are never 16MiB or longer, so the first byte of a frame is always
+// zero. A JSON line can't start with a zero byte, which is how we tell the two
+// apart from the first byte a client sends.
+//
+// Requests are answered straight from the decoded MessagePack. Integers mean
+// what they hold, and so do floats: `97.0` is the integer 97. MessagePack
+// integers are at most 64 bits, so integers bigger than that and not exactly a
+// float have to go over JSON.
+
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
+use rmpv::Value;
+use std::io::{self, Read, Write};
+
+use super::document::Document;
+use super::number::Number;
+use super::PrimeTimeError;
+
+pub const MAX_FRAME_LEN: usize = (1 << 24) - 1;
+
+// Reads the next frame, or returns `None` if the client closed the connection
+// between frames.
+pub fn read_frame<R: Read>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Option<Vec<u8>>, PrimeTimeError> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len[..1]) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(_) => return Err(PrimeTimeError::InvalidMessagePack),
+    }
+    reader
+        .read_exact(&mut len[1..])
+        .map_err(|_| PrimeTimeError::InvalidMessagePack)?;
+
+    let max_len = max_len.min(MAX_FRAME_LEN);
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(PrimeTimeError::OversizedLine(max_len));
+    }
+
+    let mut frame = vec![0; len];
+    reader
+        .read_exact(&mut frame)
+        .map_err(|_| PrimeTimeError::InvalidMessagePack)?;
+    Ok(Some(frame))
+}
+
+// Decodes a request frame. Requests can only hold what a JSON request could.
+pub fn decode(frame: &[u8]) -> Result<Value, PrimeTimeError> {
+    let mut reader = frame;
+    let value =
+        rmpv::decode::read_value(&mut reader).map_err(|_| PrimeTimeError::InvalidMessagePack)?;
+    if !reader.is_empty() {
+        // Trailing bytes after the request.
+        return Err(PrimeTimeError::InvalidMessagePack);
+    }
+    if !is_like_json(&value) {
+        return Err(PrimeTimeError::InvalidMessagePack);
+    }
+    Ok(value)
+}
+
+fn is_like_json(value: &Value) -> bool {
+    match value {
+        Value::Nil | Value::Boolean(_) | Value::Integer(_) | Value::F32(_) | Value::F64(_) => true,
+        Value::String(s) => s.is_str(),
+        Value::Array(elements) => elements.iter().all(is_like_json),
+        // JSON only has string keys.
+        Value::Map(members) => members
+            .iter()
+            .all(|(key, value)| key.is_str() && is_like_json(value)),
+        Value::Binary(_) | Value::Ext(..) => false,
+    }
+}
+
+impl Document for &Value {
+    fn null() -> Self {
+        static NIL: Value = Value::Nil;
+        &NIL
+    }
+
+    fn get(&self, key: &str) -> Option<Self> {
+        let members = self.as_map()?;
+        members
+            .iter()
+            .rev()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    fn keys(&self) -> Option<Vec<String>> {
+        let members = self.as_map()?;
+        // `decode` made sure every key's a string.
+        Some(
+            members
+                .iter()
+                .filter_map(|(key, _)| key.as_str().map(str::to_string))
+                .collect(),
+        )
+    }
+
+    fn elements(&self) -> Option<Vec<Self>> {
+        Some(self.as_array()?.iter().collect())
+    }
+
+    fn is_null(&self) -> bool {
+        Value::is_nil(self)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        Value::as_str(self)
+    }
+
+    fn as_number(&self) -> Option<Number> {
+        let n = match self {
+            Value::Integer(n) => {
+                let n = match (n.as_u64(), n.as_i64()) {
+                    (Some(n), _) => BigInt::from(n),
+                    (_, Some(n)) => BigInt::from(n),
+                    _ => unreachable!(),
+                };
+                return Some(Number::Integer(n));
+            }
+            Value::F32(n) => *n as f64,
+            Value::F64(n) => *n,
+            _ => return None,
+        };
+        // Infinities and NaN aren't numbers as far as JSON's concerned.
+        if !n.is_finite() {
+            None
+        } else if n.fract() != 0.0 {
+            Some(Number::Fraction)
+        } else {
+            BigInt::from_f64(n).map(Number::Integer)
+        }
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        match self {
+            Value::Nil => json::Null,
+            Value::Boolean(b) => (*b).into(),
+            Value::Integer(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(n), _) => n.into(),
+                (_, Some(n)) => n.into(),
+                _ => unreachable!(),
+            },
+            Value::F32(n) => (*n).into(),
+            Value::F64(n) => (*n).into(),
+            Value::String(s) => s.as_str().unwrap_or_default().into(),
+            Value::Array(elements) => {
+                json::JsonValue::Array(elements.iter().map(|e| e.to_json()).collect())
+            }
+            Value::Map(members) => {
+                let mut object = json::JsonValue::new_object();
+                for (key, value) in members {
+                    object[key.as_str().unwrap_or_default()] = value.to_json();
+                }
+                object
+            }
+            Value::Binary(_) | Value::Ext(..) => json::Null,
+        }
+    }
+}
+
+// Writes a response as a frame.
+// Returns how many bytes the frame took.
+pub fn write_frame<W: Write>(writer: &mut W, response: &json::JsonValue) -> io::Result<usize> {
+    let mut body = vec![];
+    rmpv::encode::write_value(&mut body, &from_json(response)).map_err(io::Error::other)?;
+
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend(body);
+    writer.write_all(&frame)?;
+    Ok(frame.len())
+}
+
+fn from_json(value: &json::JsonValue) -> Value {
+    match value {
+        json::JsonValue::Null => Value::Nil,
+        json::JsonValue::Boolean(b) => Value::Boolean(*b),
+        json::JsonValue::Short(s) => Value::from(s.as_str()),
+        json::JsonValue::String(s) => Value::from(s.as_str()),
+        json::JsonValue::Number(_) => match (value.as_u64(), value.as_i64()) {
+            (Some(n), _) => Value::from(n),
+            (_, Some(n)) => Value::from(n),
+            _ => Value::from(value.as_f64().unwrap()),
+        },
+        json::JsonValue::Array(elements) => Value::Array(elements.iter().map(from_json).collect()),
+        json::JsonValue::Object(members) => Value::Map(
+            members
+                .iter()
+                .map(|(key, value)| (Value::from(key), from_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, read_frame, write_frame};
+    use crate::prime_time::document::Document;
+    use crate::prime_time::number::Number;
+    use json::object;
+    use num_bigint::BigInt;
+    use rmpv::Value;
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut bytes = vec![];
+        rmpv::encode::write_value(&mut bytes, value).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_decode() {
+        let request = Value::Map(vec![
+            ("method".into(), "isPrime".into()),
+            ("number".into(), 97.into()),
+            ("list".into(), Value::Array(vec![true.into(), Value::Nil])),
+        ]);
+        assert_eq!(decode(&encode(&request)).unwrap(), request);
+
+        let mut trailing = encode(&request);
+        trailing.push(0);
+        assert!(decode(&trailing).is_err());
+        assert!(decode(&encode(&Value::Map(vec![(1.into(), 2.into())]))).is_err());
+        assert!(decode(&encode(&Value::Binary(vec![1, 2]))).is_err());
+        // An array of two with only one element.
+        assert!(decode(&[0x92, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_document() {
+        let request = Value::Map(vec![
+            ("method".into(), "isPrime".into()),
+            ("number".into(), 97.into()),
+            ("number".into(), (-4).into()),
+            ("nothing".into(), Value::Nil),
+        ]);
+        let request = &request;
+        assert_eq!(
+            request.keys().unwrap(),
+            vec!["method", "number", "number", "nothing"]
+        );
+        assert_eq!(request.get("method").unwrap().as_str(), Some("isPrime"));
+        // The last of a duplicate wins, as in JSON.
+        assert_eq!(
+            request.get("number").unwrap().as_number(),
+            Some(Number::Integer(BigInt::from(-4)))
+        );
+        assert!(request.get("nothing").unwrap().is_null());
+        assert!(request.get("missing").is_none());
+        assert!(request.get("method").unwrap().get("method").is_none());
+
+        let cases = [
+            (
+                Value::from(u64::MAX),
+                Some(Number::Integer(BigInt::from(u64::MAX))),
+            ),
+            (Value::from(97.0), Some(Number::Integer(BigInt::from(97)))),
+            (Value::from(-0.0), Some(Number::Integer(BigInt::from(0)))),
+            (Value::F32(97.5), Some(Number::Fraction)),
+            (
+                Value::from(2f64.powi(70)),
+                Some(Number::Integer(BigInt::from(1) << 70)),
+            ),
+            (Value::from(f64::NAN), None),
+            (Value::from(f64::INFINITY), None),
+            (Value::from("97"), None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!((&value).as_number(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_frames_round_trip() {
+        let response = object! {method: "isPrime", prime: true, factors: [2, 3], big: 1.5};
+        let mut bytes = vec![];
+        write_frame(&mut bytes, &response).unwrap();
+        assert_eq!(bytes[0], 0);
+
+        let mut reader = &bytes[..];
+        let frame = read_frame(&mut reader, 1024).unwrap().unwrap();
+        assert!(read_frame(&mut reader, 1024).unwrap().is_none());
+        assert_eq!((&decode(&frame).unwrap()).to_json(), response);
+
+        let mut reader = &bytes[..];
+        assert!(read_frame(&mut reader, 4).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+// How prime_time reads JSON numbers. We work on the number's raw text rather
+// than on what the `json` crate made of it, so that a number means exactly
+// what was written:
+//
+// - A number is an integer if its exact value is a whole number, however it
+//   was written. `97`, `97.0000`, `9.7e1`, `970e-1` and `1E3` are all
+//   integers; `97.5` and `1e-3` aren't.
+// - Negative zero (`-0`, `-0.0`, `-0e5`) is just zero.
+// - Negative integers are integers. Whether a method accepts them is up to
+//   the method.
+// - An integer with a big enough exponent, like `1e999999999`, would take
+//   more memory to write out than we're willing to spend. Those are
+//   `HugeInteger`s: we know they're whole multiples of ten and nothing more.
+
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::Zero;
+
+// The most zeros an exponent can add to an integer before it's a
+// `HugeInteger`.
+pub const MAX_EXPONENT: u64 = 4096;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Number {
+    Integer(BigInt),
+    // An integer too big to write out, and never zero. See above.
+    HugeInteger,
+    // A number with a fractional part.
+    Fraction,
+}
+
+// Reads the text of a JSON number. Returns `None` if `text` isn't one, going
+// by the grammar in RFC 8259: no leading `+`, no leading zeros, and digits on
+// both sides of any decimal point.
+pub fn parse(text: &str) -> Option<Number> {
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+
+    let negative = bytes.first() == Some(&b'-');
+    if negative {
+        pos += 1;
+    }
+
+    let int_digits = digits(bytes, &mut pos);
+    if int_digits.is_empty() || (int_digits.len() > 1 && int_digits[0] == b'0') {
+        return None;
+    }
+
+    let mut frac_digits: &[u8] = &[];
+    if bytes.get(pos) == Some(&b'.') {
+        pos += 1;
+        frac_digits = digits(bytes, &mut pos);
+        if frac_digits.is_empty() {
+            return None;
+        }
+    }
+
+    // Exponents are kept as an i128 so that even absurd ones can't overflow
+    // once we've capped how many digits we read of them.
+    let mut exponent: i128 = 0;
+    if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+        pos += 1;
+        let exponent_negative = match bytes.get(pos) {
+            Some(b'-') => {
+                pos += 1;
+                true
+            }
+            Some(b'+') => {
+                pos += 1;
+                false
+            }
+            _ => false,
+        };
+        let exponent_digits = digits(bytes, &mut pos);
+        if exponent_digits.is_empty() {
+            return None;
+        }
+        exponent = saturating_parse(exponent_digits);
+        if exponent_negative {
+            exponent = -exponent;
+        }
+    }
+
+    if pos != bytes.len() {
+        return None;
+    }
+
+    // The value is `significand * 10^exponent` once the fraction digits are
+    // moved into the significand.
+    let mut significand: Vec<u8> = int_digits.iter().chain(frac_digits).copied().collect();
+    exponent -= frac_digits.len() as i128;
+
+    // Trailing zeros can come off the significand without changing the value.
+    while significand.len() > 1 && significand.last() == Some(&b'0') {
+        significand.pop();
+        exponent += 1;
+    }
+
+    let significand = BigUint::parse_bytes(&significand, 10)?;
+    if significand.is_zero() {
+        return Some(Number::Integer(BigInt::zero()));
+    }
+    if exponent < 0 {
+        // With the trailing zeros gone, the last digit isn't zero, so
+        // dividing by any power of ten leaves a fraction.
+        return Some(Number::Fraction);
+    }
+    if exponent > MAX_EXPONENT as i128 {
+        return Some(Number::HugeInteger);
+    }
+
+    let magnitude = significand * BigUint::from(10u32).pow(exponent as u32);
+    let sign = if negative { Sign::Minus } else { Sign::Plus };
+    Some(Number::Integer(BigInt::from_biguint(sign, magnitude)))
+}
+
+// Advances `pos` past a run of ASCII digits and returns them.
+fn digits<'a>(bytes: &'a [u8], pos: &mut usize) -> &'a [u8] {
+    let start = *pos;
+    while matches!(bytes.get(*pos), Some(b'0'..=b'9')) {
+        *pos += 1;
+    }
+    &bytes[start..*pos]
+}
+
+// Anything past this is far beyond `MAX_EXPONENT` (or any fraction digits
+// that could fit on a line) anyway.
+const EXPONENT_CAP: i128 = 1 << 64;
+
+fn saturating_parse(digits: &[u8]) -> i128 {
+    digits.iter().fold(0, |acc, d| {
+        (acc * 10 + (d - b'0') as i128).min(EXPONENT_CAP)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Number};
+    use num_bigint::BigInt;
+
+    fn integer(n: &str) -> Option<Number> {
+        Some(Number::Integer(n.parse::<BigInt>().unwrap()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let cases = [
+            // Plain integers.
+            ("0", integer("0")),
+            ("97", integer("97")),
+            ("-97", integer("-97")),
+            (
+                "170141183460469231731687303715884105727",
+                integer("170141183460469231731687303715884105727"),
+            ),
+            // Negative zero is zero.
+            ("-0", integer("0")),
+            ("-0.0", integer("0")),
+            ("-0e5", integer("0")),
+            ("0e-5", integer("0")),
+            // Whole numbers written with a fraction.
+            ("1.0", integer("1")),
+            ("97.0000", integer("97")),
+            ("-97.0", integer("-97")),
+            // Whole numbers written with an exponent.
+            ("1e3", integer("1000")),
+            ("1E3", integer("1000")),
+            ("1e+3", integer("1000")),
+            ("9.7e1", integer("97")),
+            ("970e-1", integer("97")),
+            ("0.97e2", integer("97")),
+            ("1e0", integer("1")),
+            ("1e-0", integer("1")),
+            ("1e4096", integer(&format!("1{}", "0".repeat(4096)))),
+            // Too big to write out.
+            ("1e4097", Some(Number::HugeInteger)),
+            ("-1e4097", Some(Number::HugeInteger)),
+            ("1e99999999999999999999999999999", Some(Number::HugeInteger)),
+            // Fractions.
+            ("97.5", Some(Number::Fraction)),
+            ("-0.5", Some(Number::Fraction)),
+            ("1e-3", Some(Number::Fraction)),
+            ("971e-1", Some(Number::Fraction)),
+            ("1.23e1", Some(Number::Fraction)),
+            ("1e-99999999999999999999999999999", Some(Number::Fraction)),
+            // Not JSON numbers.
+            ("", None),
+            ("-", None),
+            ("+1", None),
+            ("01", None),
+            ("-01", None),
+            ("1.", None),
+            (".5", None),
+            ("1e", None),
+            ("1e+", None),
+            ("0x10", None),
+            ("1 ", None),
+            ("Infinity", None),
+            ("NaN", None),
+            ("\"97\"", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse(text), expected, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use num_bigint::{BigInt, BigUint, RandBigInt, Sign};
+use num_integer::Integer;
+use num_traits::{One, ToPrimitive, Zero};
+
+// Primes we trial divide by before doing anything expensive.
+const SMALL_PRIMES: [u32; 25] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+
+// Returns whether `n` is prime.
+//
+// Anything that fits in a u64 is checked deterministically by `primal`. Larger
+// numbers get the Baillie-PSW test: a strong probable prime test to base 2
+// followed by a strong Lucas probable prime test. No composite is known to
+// pass both, and none exist below 2^64.
+pub fn is_prime(n: &BigUint) -> bool {
+    if let Some(n) = n.to_u64() {
+        return primal::is_prime(n);
+    }
+
+    for p in SMALL_PRIMES {
+        if (n % p).is_zero() {
+            return false;
+        }
+    }
+
+    is_strong_probable_prime(n, &BigUint::from(2u32)) && is_strong_lucas_probable_prime(n)
+}
+
+// Whether `n` is more than `max_digits` digits long.
+pub fn has_more_digits_than(n: &BigUint, max_digits: u32) -> bool {
+    *n >= BigUint::from(10u32).pow(max_digits)
+}
+
+// Whether a small prime other than `n` itself divides `n`, which gives away
+// that it's composite however long it is.
+pub fn has_small_factor(n: &BigUint) -> bool {
+    SMALL_PRIMES
+        .iter()
+        .any(|&p| (n % p).is_zero() && *n != BigUint::from(p))
+}
+
+// Miller-Rabin with `rounds` random bases. A composite passes with probability
+// at most 4^-rounds, and a prime always passes.
+pub fn is_probable_prime(n: &BigUint, rounds: u32) -> bool {
+    for p in SMALL_PRIMES {
+        if *n == BigUint::from(p) {
+            return true;
+        }
+        if (n % p).is_zero() {
+            return false;
+        }
+    }
+    if *n < BigUint::from(97u32 * 97) {
+        // Anything this small with no small prime factor is prime, except 1.
+        return *n > BigUint::one();
+    }
+
+    // Bases are drawn from [2, n - 2].
+    let mut rng = rand::thread_rng();
+    let (low, high) = (BigUint::from(2u32), n - 1u32);
+    (0..rounds).all(|_| is_strong_probable_prime(n, &rng.gen_biguint_range(&low, &high)))
+}
+
+// The smallest prime greater than `n`, if it fits in a u64.
+pub fn next_prime(n: u64) -> Option<u64> {
+    let mut candidate = n.checked_add(1)?.max(2);
+    while !primal::is_prime(candidate) {
+        candidate = candidate.checked_add(1)?;
+    }
+    Some(candidate)
+}
+
+// The prime factors of `n`, with multiplicity, smallest first. 0 and 1 have
+// none.
+pub fn factorize(mut n: u64) -> Vec<u64> {
+    let mut factors = vec![];
+    if n < 2 {
+        return factors;
+    }
+
+    for p in SMALL_PRIMES {
+        let p = p as u64;
+        while (n % p).is_zero() {
+            factors.push(p);
+            n /= p;
+        }
+    }
+
+    let mut unsplit = vec![n];
+    while let Some(m) = unsplit.pop() {
+        if m == 1 {
+            continue;
+        }
+        if primal::is_prime(m) {
+            factors.push(m);
+        } else {
+            let d = pollard_rho(m);
+            unsplit.push(d);
+            unsplit.push(m / d);
+        }
+    }
+
+    factors.sort_unstable();
+    factors
+}
+
+// Finds a non-trivial factor of `n`, which must be composite and have no
+// small prime factors.
+fn pollard_rho(n: u64) -> u64 {
+    let mul_mod = |a: u64, b: u64| ((a as u128 * b as u128) % n as u128) as u64;
+
+    for c in 1.. {
+        let f = |x: u64| ((mul_mod(x, x) as u128 + c as u128) % n as u128) as u64;
+        let (mut x, mut y, mut d) = (2, 2, 1);
+        while d == 1 {
+            x = f(x);
+            y = f(f(y));
+            d = x.abs_diff(y).gcd(&n);
+        }
+        if d != n {
+            return d;
+        }
+    }
+    unreachable!()
+}
+
+// Miller-Rabin for a single base. `n` must be odd and greater than `base`.
+fn is_strong_probable_prime(n: &BigUint, base: &BigUint) -> bool {
+    let n_minus_one = n - 1u32;
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+
+    let mut x = base.modpow(&d, n);
+    if x.is_one() || x == n_minus_one {
+        return true;
+    }
+    for _ in 1..s {
+        x = x.modpow(&BigUint::from(2u32), n);
+        if x == n_minus_one {
+            return true;
+        }
+    }
+    false
+}
+
+// The strong Lucas test with parameters chosen by Selfridge's method A. `n`
+// must be odd and have no small prime factors.
+fn is_strong_lucas_probable_prime(n: &BigUint) -> bool {
+    // Selfridge's search below never ends for perfect squares.
+    let root = n.sqrt();
+    if &root * &root == *n {
+        return false;
+    }
+
+    let n = BigInt::from_biguint(Sign::Plus, n.clone());
+
+    // Find the first D in 5, -7, 9, -11, ... with Jacobi symbol (D/n) = -1.
+    let mut d = BigInt::from(5);
+    loop {
+        match jacobi(&d, &n) {
+            -1 => break,
+            // D shares a factor with n. Only n itself could be prime then, and
+            // n is bigger than any D we'd get to.
+            0 => return false,
+            _ => {}
+        }
+        d = if d.sign() == Sign::Plus {
+            -(d + 2u32)
+        } else {
+            -(d - 2u32)
+        };
+    }
+
+    let p = BigInt::one();
+    let q = (BigInt::one() - &d) / 4u32;
+
+    // n + 1 = k * 2^s with k odd.
+    let n_plus_one = &n + 1u32;
+    let s = n_plus_one.trailing_zeros().unwrap_or(0);
+    let k = &n_plus_one >> s;
+
+    // Work out U_k, V_k and Q^k mod n, walking the bits of k from the top.
+    let mut u = BigInt::one();
+    let mut v = p.clone();
+    let mut q_k = modulo(&q, &n);
+    for i in (0..k.bits() - 1).rev() {
+        u = modulo(&(&u * &v), &n);
+        v = modulo(&(&v * &v - 2u32 * &q_k), &n);
+        q_k = modulo(&(&q_k * &q_k), &n);
+        if k.bit(i) {
+            let next_u = half(&(&p * &u + &v), &n);
+            let next_v = half(&(&d * &u + &p * &v), &n);
+            u = next_u;
+            v = next_v;
+            q_k = modulo(&(&q_k * &q), &n);
+        }
+    }
+
+    if u.is_zero() || v.is_zero() {
+        return true;
+    }
+    for _ in 1..s {
+        v = modulo(&(&v * &v - 2u32 * &q_k), &n);
+        if v.is_zero() {
+            return true;
+        }
+        q_k = modulo(&(&q_k * &q_k), &n);
+    }
+    false
+}
+
+// The Jacobi symbol (a/n) for odd, positive n.
+fn jacobi(a: &BigInt, n: &BigInt) -> i32 {
+    let mut a = modulo(a, n);
+    let mut n = n.clone();
+    let mut result = 1;
+
+    while !a.is_zero() {
+        while a.is_even() {
+            a >>= 1;
+            let n_mod_8 = (&n % 8u32).to_u32().unwrap();
+            if n_mod_8 == 3 || n_mod_8 == 5 {
+                result = -result;
+            }
+        }
+        std::mem::swap(&mut a, &mut n);
+        if (&a % 4u32).to_u32() == Some(3) && (&n % 4u32).to_u32() == Some(3) {
+            result = -result;
+        }
+        a = modulo(&a, &n);
+    }
+
+    if n.is_one() {
+        result
+    } else {
+        0
+    }
+}
+
+// x mod n, always in [0, n).
+fn modulo(x: &BigInt, n: &BigInt) -> BigInt {
+    x.mod_floor(n)
+}
+
+// x / 2 mod n for odd n.
+fn half(x: &BigInt, n: &BigInt) -> BigInt {
+    let x = modulo(x, n);
+    if x.is_odd() {
+        (x + n) >> 1
+    } else {
+        x >> 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        factorize, has_more_digits_than, has_small_factor, is_prime, is_probable_prime, next_prime,
+    };
+    use num_bigint::BigUint;
+
+    fn big(digits: &str) -> BigUint {
+        digits.parse().unwrap()
+    }
+
+    #[test]
+    fn test_agrees_with_sieve_for_small_numbers() {
+        let sieve = primal::Sieve::new(10_000);
+        for n in 0..10_000u32 {
+            assert_eq!(
+                is_prime(&BigUint::from(n)),
+                sieve.is_prime(n as usize),
+                "{}",
+                n
+            );
+        }
+    }
+
+    #[test]
+    fn test_large_primes() {
+        // 2^89 - 1, 2^107 - 1 and 2^127 - 1 are Mersenne primes.
+        assert!(is_prime(&big("618970019642690137449562111")));
+        assert!(is_prime(&big("162259276829213363391578010288127")));
+        assert!(is_prime(&big("170141183460469231731687303715884105727")));
+        // The smallest prime above 2^64.
+        assert!(is_prime(&big("18446744073709551629")));
+    }
+
+    #[test]
+    fn test_large_composites() {
+        // 2^64 + 1 = 274177 * 67280421310721.
+        assert!(!is_prime(&big("18446744073709551617")));
+        // (2^61 - 1) * (2^89 - 1).
+        assert!(!is_prime(&big(
+            "1427247692705959880439315947500961989719490561"
+        )));
+        // The square of a prime above 2^32.
+        assert!(!is_prime(&big("18446744202558570721")));
+        // A strong pseudoprime to every prime base up to 37.
+        assert!(!is_prime(&big("3317044064679887385961981")));
+        assert!(!is_prime(&big("1000000000000000000000000000000")));
+    }
+
+    #[test]
+    fn test_is_probable_prime() {
+        let sieve = primal::Sieve::new(10_000);
+        for n in 0..10_000u32 {
+            assert_eq!(
+                is_probable_prime(&BigUint::from(n), 10),
+                sieve.is_prime(n as usize),
+                "{}",
+                n
+            );
+        }
+        assert!(is_probable_prime(
+            &big("170141183460469231731687303715884105727"),
+            20
+        ));
+        assert!(!is_probable_prime(&big("3317044064679887385961981"), 20));
+    }
+
+    #[test]
+    fn test_has_more_digits_than() {
+        assert!(!has_more_digits_than(&big("999"), 3));
+        assert!(has_more_digits_than(&big("1000"), 3));
+    }
+
+    #[test]
+    fn test_has_small_factor() {
+        assert!(has_small_factor(&big("4")));
+        assert!(has_small_factor(&big("1000000000000000000000000000001")));
+        // Small primes don't count as factors of themselves.
+        assert!(!has_small_factor(&big("97")));
+        // (2^61 - 1) * (2^89 - 1) has only large factors.
+        assert!(!has_small_factor(&big(
+            "1427247692705959880439315947500961989719490561"
+        )));
+    }
+
+    #[test]
+    fn test_next_prime() {
+        assert_eq!(next_prime(0), Some(2));
+        assert_eq!(next_prime(2), Some(3));
+        assert_eq!(next_prime(97), Some(101));
+        assert_eq!(next_prime(18446744073709551556), Some(18446744073709551557));
+        assert_eq!(next_prime(18446744073709551557), None);
+    }
+
+    #[test]
+    fn test_factorize() {
+        assert_eq!(factorize(0), Vec::<u64>::new());
+        assert_eq!(factorize(1), Vec::<u64>::new());
+        assert_eq!(factorize(97), vec![97]);
+        assert_eq!(factorize(360), vec![2, 2, 2, 3, 3, 5]);
+        assert_eq!(
+            factorize(18446744073709551615),
+            vec![3, 5, 17, 257, 641, 65537, 6700417]
+        );
+        // Two primes just above 2^31.
+        assert_eq!(
+            factorize(2147483659 * 2147483693),
+            vec![2147483659, 2147483693]
+        );
+        assert_eq!(factorize(10403 * 10403), vec![101, 101, 103, 103]);
+    }
+}
@@ -0,0 +1,184 @@
+// The `json` crate turns numbers into a u64 mantissa and an exponent, which
+// loses digits for big integers. This gets at the text of an object's
+// members as they were sent so that we can interpret them ourselves.
+//
+// Only use this on text that `json::parse` has already accepted: it leans on
+// the input being valid JSON and doesn't check it again.
+
+// Returns the keys and raw value text of the members of a top-level JSON
+// object, in the order they appear. Returns `None` if `text` isn't an object.
+pub fn object_members(text: &str) -> Option<Vec<(String, &str)>> {
+    let bytes = text.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+    if bytes.get(pos) != Some(&b'{') {
+        return None;
+    }
+    pos = skip_whitespace(bytes, pos + 1);
+
+    let mut members = vec![];
+    if bytes.get(pos) == Some(&b'}') {
+        return Some(members);
+    }
+
+    loop {
+        let key_end = string_end(bytes, pos)?;
+        // Let the `json` crate deal with any escapes in the key.
+        let key = json::parse(&text[pos..key_end]).ok()?.as_str()?.to_string();
+
+        pos = skip_whitespace(bytes, key_end);
+        if bytes.get(pos) != Some(&b':') {
+            return None;
+        }
+        pos = skip_whitespace(bytes, pos + 1);
+
+        let value_end = value_end(bytes, pos)?;
+        members.push((key, &text[pos..value_end]));
+
+        pos = skip_whitespace(bytes, value_end);
+        match bytes.get(pos) {
+            Some(b',') => pos = skip_whitespace(bytes, pos + 1),
+            Some(b'}') => return Some(members),
+            _ => return None,
+        }
+    }
+}
+
+// Returns the raw text of the value for `key` in a top-level JSON object. If
+// the key appears more than once the last one wins, as it does in `json`.
+pub fn member<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    object_members(text)?
+        .into_iter()
+        .rev()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v)
+}
+
+// Returns the raw text of each element of a top-level JSON array, in order.
+// Returns `None` if `text` isn't an array.
+pub fn array_elements(text: &str) -> Option<Vec<&str>> {
+    let bytes = text.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+    if bytes.get(pos) != Some(&b'[') {
+        return None;
+    }
+    pos = skip_whitespace(bytes, pos + 1);
+
+    let mut elements = vec![];
+    if bytes.get(pos) == Some(&b']') {
+        return Some(elements);
+    }
+
+    loop {
+        let element_end = value_end(bytes, pos)?;
+        elements.push(&text[pos..element_end]);
+
+        pos = skip_whitespace(bytes, element_end);
+        match bytes.get(pos) {
+            Some(b',') => pos = skip_whitespace(bytes, pos + 1),
+            Some(b']') => return Some(elements),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while matches!(bytes.get(pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+        pos += 1;
+    }
+    pos
+}
+
+// `pos` is the opening quote. Returns the index just past the closing quote.
+fn string_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    if bytes.get(pos) != Some(&b'"') {
+        return None;
+    }
+    let mut i = pos + 1;
+    loop {
+        match bytes.get(i)? {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+}
+
+// Returns the index just past the value starting at `pos`.
+fn value_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    match bytes.get(pos)? {
+        b'"' => string_end(bytes, pos),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut i = pos;
+            loop {
+                match bytes.get(i)? {
+                    b'"' => {
+                        i = string_end(bytes, i)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+        // Numbers, booleans and null run until the next delimiter.
+        _ => {
+            let mut i = pos;
+            while !matches!(
+                bytes.get(i),
+                None | Some(b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r')
+            ) {
+                i += 1;
+            }
+            Some(i)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{array_elements, member, object_members};
+
+    #[test]
+    fn test_object_members() {
+        let text = r#" { "method" : "isPrime", "number":123456789012345678901234567890 ,
+            "nested": {"a": [1, "}", {"b": "\"]"}]}, "flag": true, "none": null } "#;
+
+        assert_eq!(
+            object_members(text).unwrap(),
+            vec![
+                ("method".to_string(), r#""isPrime""#),
+                ("number".to_string(), "123456789012345678901234567890"),
+                ("nested".to_string(), r#"{"a": [1, "}", {"b": "\"]"}]}"#),
+                ("flag".to_string(), "true"),
+                ("none".to_string(), "null"),
+            ]
+        );
+        assert_eq!(object_members("{}").unwrap(), vec![]);
+        assert_eq!(object_members("[1, 2]"), None);
+        assert_eq!(object_members("12"), None);
+    }
+
+    #[test]
+    fn test_member_uses_last_duplicate() {
+        assert_eq!(member(r#"{"number": 1, "number": 2}"#, "number"), Some("2"));
+        assert_eq!(member(r#"{"number": 1}"#, "method"), None);
+    }
+
+    #[test]
+    fn test_array_elements() {
+        assert_eq!(
+            array_elements(r#" [ {"a": [1, 2]}, 3.5e2 ,"x,]", [] ] "#).unwrap(),
+            vec![r#"{"a": [1, 2]}"#, "3.5e2", r#""x,]""#, "[]"]
+        );
+        assert_eq!(array_elements("[]").unwrap(), Vec::<&str>::new());
+        assert_eq!(array_elements("{}"), None);
+    }
+}
@@ -0,0 +1,247 @@
+// The HAProxy PROXY protocol, versions 1 and 2, as spoken by load balancers
+// that want us to know who the client really is.
+//
+// See https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+// Including the CRLF, per the spec.
+const V1_MAX_LEN: usize = 107;
+
+// Proxies send the header straight away, so anything slower isn't one, however
+// it trickles in.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("Couldn't read PROXY header: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Connection didn't start with a PROXY header")]
+    Missing,
+
+    #[error("Malformed PROXY header: {0}")]
+    Malformed(&'static str),
+}
+
+// Reads the PROXY header from a freshly accepted connection and returns the
+// address of the client behind it.
+pub fn read_client_address(stream: &TcpStream) -> Result<SocketAddr, ProxyError> {
+    let mut reader = DeadlineReader {
+        stream,
+        deadline: Instant::now() + HEADER_TIMEOUT,
+    };
+    let client = read_header(&mut reader)?;
+    stream.set_read_timeout(None)?;
+    match client {
+        Some(client) => Ok(client),
+        None => Ok(stream.peer_addr()?),
+    }
+}
+
+// Reads from a stream until `deadline`, rather than for a while after each
+// byte.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        (&mut &*self.stream).read(buf)
+    }
+}
+
+// Reads a PROXY header, v1 or v2, from the start of `stream`, leaving
+// everything after it unread. Returns the address of the client the proxy
+// is speaking for, or None if the proxy didn't say (e.g. a health check).
+pub fn read_header<R: Read>(stream: &mut R) -> Result<Option<SocketAddr>, ProxyError> {
+    // The shortest v1 header is longer than this, so it's safe to read either
+    // way.
+    let mut start = [0; 12];
+    stream.read_exact(&mut start)?;
+
+    if start == V2_SIGNATURE {
+        read_v2(stream)
+    } else if start.starts_with(V1_PREFIX) {
+        read_v1(stream, &start)
+    } else {
+        Err(ProxyError::Missing)
+    }
+}
+
+// e.g. "PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n"
+fn read_v1<R: Read>(stream: &mut R, start: &[u8]) -> Result<Option<SocketAddr>, ProxyError> {
+    // A byte at a time, so as not to read past the end of the line.
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(ProxyError::Malformed("v1 header too long"));
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line[V1_PREFIX.len()..line.len() - 2])
+        .map_err(|_| ProxyError::Malformed("v1 header isn't text"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        [protocol @ ("TCP4" | "TCP6"), source, _destination, source_port, _destination_port] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| ProxyError::Malformed("bad v1 source address"))?;
+            if ip.is_ipv4() != (*protocol == "TCP4") {
+                return Err(ProxyError::Malformed("v1 address doesn't match protocol"));
+            }
+            let port = source_port
+                .parse()
+                .map_err(|_| ProxyError::Malformed("bad v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(ProxyError::Malformed("unrecognised v1 header")),
+    }
+}
+
+fn read_v2<R: Read>(stream: &mut R) -> Result<Option<SocketAddr>, ProxyError> {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header)?;
+    let [version_command, family, len @ ..] = header;
+    let mut addresses = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut addresses)?;
+
+    if version_command >> 4 != 2 {
+        return Err(ProxyError::Malformed("unsupported version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL: the proxy's talking to us on its own account.
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(ProxyError::Malformed("unsupported v2 command")),
+    }
+
+    // Whatever's left over after the addresses is TLVs, which we don't need.
+    let too_short = ProxyError::Malformed("v2 addresses truncated");
+    match family {
+        // TCP or UDP over IPv4.
+        0x11 | 0x12 => {
+            let a = addresses.get(..12).ok_or(too_short)?;
+            let ip = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+            let port = u16::from_be_bytes([a[8], a[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // TCP or UDP over IPv6.
+        0x21 | 0x22 => {
+            let a = addresses.get(..36).ok_or(too_short)?;
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&a[..16]).unwrap());
+            let port = u16::from_be_bytes([a[32], a[33]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // Unix sockets and unspecified families have no address we can use.
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_header, ProxyError, V2_SIGNATURE};
+    use std::io::Read;
+
+    fn v2(version_command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(version_command);
+        header.push(family);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    #[test]
+    fn test_read_header() {
+        let ipv4 = [192, 168, 0, 1, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb];
+        let mut ipv6 = vec![0; 36];
+        ipv6[..16].copy_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        ipv6[32..34].copy_from_slice(&8080u16.to_be_bytes());
+        let mut ipv4_with_tlvs = ipv4.to_vec();
+        ipv4_with_tlvs.extend([0x04, 0x00, 0x01, 0xff]);
+
+        let cases: Vec<(Vec<u8>, Option<&str>)> = vec![
+            (
+                b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\n".to_vec(),
+                Some("192.168.0.1:56324"),
+            ),
+            (
+                b"PROXY TCP6 2001:db8::1 2001:db8::2 8080 443\r\n".to_vec(),
+                Some("[2001:db8::1]:8080"),
+            ),
+            (b"PROXY UNKNOWN\r\n".to_vec(), None),
+            (
+                b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n".to_vec(),
+                None,
+            ),
+            (v2(0x21, 0x11, &ipv4), Some("192.168.0.1:56324")),
+            (v2(0x21, 0x11, &ipv4_with_tlvs), Some("192.168.0.1:56324")),
+            (v2(0x21, 0x21, &ipv6), Some("[2001:db8::1]:8080")),
+            (v2(0x20, 0x00, &[]), None),
+            (v2(0x21, 0x31, &[0; 216]), None),
+        ];
+        for (header, expected) in cases {
+            // Whatever follows the header is left for the service.
+            let stream = [header.as_slice(), b"payload"].concat();
+            let mut reader = stream.as_slice();
+            let client = read_header(&mut reader).unwrap();
+            assert_eq!(
+                client.map(|c| c.to_string()).as_deref(),
+                expected,
+                "{:?}",
+                header
+            );
+            let mut rest = vec![];
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, b"payload");
+        }
+    }
+
+    #[test]
+    fn test_read_bad_header() {
+        let cases: Vec<Vec<u8>> = vec![
+            b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 56324\r\n".to_vec(),
+            b"PROXY TCP4 2001:db8::1 10.0.0.1 56324 443\r\n".to_vec(),
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 99999 443\r\n".to_vec(),
+            [b"PROXY TCP4 ".as_slice(), &[b'1'; 200]].concat(),
+            v2(0x11, 0x11, &[0; 12]),
+            v2(0x22, 0x11, &[0; 12]),
+            v2(0x21, 0x11, &[0; 8]),
+        ];
+        for header in cases {
+            assert!(
+                matches!(
+                    read_header(&mut header.as_slice()),
+                    Err(ProxyError::Missing | ProxyError::Malformed(_))
+                ),
+                "{:?}",
+                header
+            );
+        }
+    }
+}
@@ -1,43 +1,187 @@
-use std::io::{BufReader, Read, Write};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
 use std::net::{Shutdown, TcpStream};
-use std::str;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use log::debug;
+use thiserror::Error;
 
-pub fn handle_connection(mut stream: TcpStream) {
-    debug!("Handling a connection.");
-
-    debug!("Reading bytes sent by client.");
-    // Read bytes from the stream.
-    let mut reader = BufReader::new(&stream);
-    let mut buf = vec![];
-    reader.read_to_end(&mut buf).unwrap();
-
-    debug!("Writing bytes back.");
-    // Write them back, verbatim.
-    stream.write_all(&buf).unwrap();
-
-    debug!("Connection handled.");
-}
-
-pub fn run_client(url: Option<String>, bytes: &[u8]) {
-    debug!(
-        "Writing {} to server.",
-        String::from_utf8(bytes.to_vec()).unwrap()
-    );
-    let url = match url {
-        Some(url_string) => url_string,
-        None => "127.0.0.1:5001".to_string(),
-    };
-
-    let mut stream = TcpStream::connect(url).unwrap();
-    stream.write_all(bytes).unwrap();
-    stream.shutdown(Shutdown::Write).unwrap();
-    debug!("Bytes written.");
-
-    debug!("Reading bytes from server.");
-    let mut reader = BufReader::new(&stream);
-    let mut buf = vec![];
-    let _bytes_read = reader.read_to_end(&mut buf).unwrap();
-    debug!("Bytes read: {}", str::from_utf8(&buf).unwrap());
+use crate::Connection;
+
+// The most we hold of a client's bytes at once. Anything more waits in the
+// socket until we've written this much back.
+const ECHO_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn handle_connection(stream: TcpStream, mut connection: Connection) {
+    let client = connection.client;
+    debug!("Handling a connection from {}.", client);
+
+    match echo(&stream, &mut connection) {
+        Ok(echoed) => debug!(
+            "Connection from {} handled. Echoed {} bytes.",
+            client, echoed
+        ),
+        Err(e) => debug!("Error echoing to {}: {}", client, e),
+    }
+}
+
+// Writes bytes back, verbatim, as soon as they arrive. Once the client's
+// finished sending, so are we. Returns how many bytes were echoed.
+fn echo(mut stream: &TcpStream, connection: &mut Connection) -> io::Result<u64> {
+    let mut buf = vec![0; ECHO_BUFFER_SIZE];
+    let mut echoed = 0;
+
+    loop {
+        let bytes_read = match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        connection.record_received(bytes_read);
+        stream.write_all(&buf[..bytes_read])?;
+        connection.record_sent(bytes_read);
+        echoed += bytes_read as u64;
+    }
+
+    // Pass the client's half-close on.
+    connection.release_limits();
+    stream.shutdown(Shutdown::Write)?;
+    Ok(echoed)
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Couldn't connect to {0}: {1}")]
+    Connect(String, io::Error),
+
+    #[error("Error talking to the server: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Echo differs from what was sent, starting at byte {0}.")]
+    Mismatch(u64),
+
+    #[error("Server echoed {received} bytes, but {sent} were sent.")]
+    WrongLength { sent: u64, received: u64 },
+}
+
+// How an echo went.
+#[derive(Debug)]
+pub struct ClientReport {
+    pub bytes: u64,
+    // Time until the first byte came back, if there were any.
+    pub round_trip: Option<Duration>,
+    // Time until the whole echo came back.
+    pub elapsed: Duration,
+}
+
+impl ClientReport {
+    // In bytes per second, counting the payload once.
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for ClientReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Echoed {} bytes in {:?}", self.bytes, self.elapsed)?;
+        if let Some(round_trip) = self.round_trip {
+            write!(f, ", first byte back after {:?}", round_trip)?;
+        }
+        write!(f, " ({:.0} bytes/s).", self.throughput())
+    }
+}
+
+// Reads a payload to send from the file at `path`, or from stdin if it's `-`.
+pub fn read_payload(path: &Path) -> io::Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut payload = vec![];
+        io::stdin().read_to_end(&mut payload)?;
+        Ok(payload)
+    } else {
+        fs::read(path)
+    }
+}
+
+// Sends `payload` to the echo server at `address`, writing what comes back to
+// `output` as it arrives, and checks that the echo matches byte for byte.
+pub fn run_client<W: Write>(
+    address: &str,
+    payload: &[u8],
+    output: &mut W,
+) -> Result<ClientReport, ClientError> {
+    let stream =
+        TcpStream::connect(address).map_err(|e| ClientError::Connect(address.to_string(), e))?;
+    let start = Instant::now();
+
+    thread::scope(|scope| {
+        // Send while we read, or a big payload would fill the socket buffers
+        // both ways and leave us both waiting on each other.
+        let writer = scope.spawn(|| -> io::Result<()> {
+            let mut stream = &stream;
+            stream.write_all(payload)?;
+            stream.shutdown(Shutdown::Write)?;
+            debug!("Bytes written.");
+            Ok(())
+        });
+
+        let result = read_echo(&stream, payload, output, start);
+        if result.is_err() {
+            // Stop the writer if it's still going.
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        let written = writer.join().unwrap();
+        let report = result?;
+        written?;
+        Ok(report)
+    })
+}
+
+fn read_echo<W: Write>(
+    mut stream: &TcpStream,
+    payload: &[u8],
+    output: &mut W,
+    start: Instant,
+) -> Result<ClientReport, ClientError> {
+    let mut buf = vec![0; ECHO_BUFFER_SIZE];
+    let mut received = 0;
+    let mut round_trip = None;
+
+    loop {
+        let bytes_read = match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        round_trip.get_or_insert_with(|| start.elapsed());
+
+        let chunk = &buf[..bytes_read];
+        output.write_all(chunk)?;
+
+        let expected = &payload[received.min(payload.len())..];
+        if let Some(i) = chunk.iter().zip(expected).position(|(a, b)| a != b) {
+            return Err(ClientError::Mismatch((received + i) as u64));
+        }
+        received += bytes_read;
+        if received > payload.len() {
+            break;
+        }
+    }
+    output.flush()?;
+
+    if received != payload.len() {
+        return Err(ClientError::WrongLength {
+            sent: payload.len() as u64,
+            received: received as u64,
+        });
+    }
+    Ok(ClientReport {
+        bytes: received as u64,
+        round_trip,
+        elapsed: start.elapsed(),
+    })
 }
@@ -0,0 +1,245 @@
+// TLS termination in front of any service.
+//
+// The handlers all want a `TcpStream` (they peek, clone and half-close it), so
+// rather than teach each of them about TLS we decrypt on the way in and hand
+// them one end of a loopback connection. Two relay threads shuttle plaintext
+// between that and the encrypted client connection.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use log::debug;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use thiserror::Error;
+
+use crate::metrics;
+
+// Clients that haven't finished the handshake by now are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Couldn't read {0}: {1}")]
+    Read(PathBuf, io::Error),
+
+    #[error("No certificates in {0}")]
+    NoCertificates(PathBuf),
+
+    #[error("No private key in {0}")]
+    NoPrivateKey(PathBuf),
+
+    #[error("Bad TLS configuration: {0}")]
+    Rustls(#[from] rustls::Error),
+
+    #[error("Bad client CA certificates: {0}")]
+    ClientVerifier(#[from] rustls::server::VerifierBuilderError),
+}
+
+// Builds a server configuration from a PEM certificate chain and private key.
+// With `client_ca`, clients must present a certificate signed by one of the
+// CA certificates in that file.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for ca in read_certs(path)? {
+                roots.add(ca)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder.with_single_cert(read_certs(cert)?, read_key(key)?)?;
+    Ok(Arc::new(config))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let read_error = |e| TlsError::Read(path.to_path_buf(), e);
+    let mut reader = BufReader::new(File::open(path).map_err(read_error)?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(read_error)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let read_error = |e| TlsError::Read(path.to_path_buf(), e);
+    let mut reader = BufReader::new(File::open(path).map_err(read_error)?);
+    rustls_pemfile::private_key(&mut reader)
+        .map_err(read_error)?
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_path_buf()))
+}
+
+// Does the TLS handshake with the client on `stream`, then returns a plaintext
+// stream carrying the same conversation for a handler to use.
+pub(crate) fn terminate(
+    stream: TcpStream,
+    config: Arc<ServerConfig>,
+    relays: &crate::Relays,
+) -> io::Result<TcpStream> {
+    let mut conn = ServerConnection::new(config).map_err(io::Error::other)?;
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    while conn.is_handshaking() {
+        if let Err(e) = conn.complete_io(&mut &stream) {
+            metrics::increment("tls.handshake_failures");
+            return Err(e);
+        }
+    }
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    metrics::increment("tls.handshakes");
+
+    let (plaintext, handler_end) = crate::loopback_pair()?;
+    let tunnel = Arc::new(Tunnel {
+        conn: Mutex::new(conn),
+        writer: Mutex::new(stream.try_clone()?),
+        stream,
+        plaintext,
+    });
+    let decrypter = tunnel.clone();
+    thread::spawn(move || decrypter.decrypt_incoming());
+    relays.spawn(move || tunnel.encrypt_outgoing());
+
+    Ok(handler_end)
+}
+
+struct Tunnel {
+    conn: Mutex<ServerConnection>,
+    // The encrypted connection to the client. Everything written to it goes
+    // through `writer`, so records from both relay threads go out in order.
+    stream: TcpStream,
+    writer: Mutex<TcpStream>,
+    // Our end of the handler's plaintext stream.
+    plaintext: TcpStream,
+}
+
+impl Tunnel {
+    // Relays from the client to the handler.
+    fn decrypt_incoming(&self) {
+        match self.relay_incoming() {
+            Ok(()) => {
+                // The client's done sending, so the handler's done reading.
+                let _ = self.plaintext.shutdown(Shutdown::Write);
+            }
+            Err(e) => {
+                debug!("TLS connection failed: {}", e);
+                let _ = self.stream.shutdown(Shutdown::Both);
+                let _ = self.plaintext.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    fn relay_incoming(&self) -> io::Result<()> {
+        let mut buf = [0; RELAY_BUFFER_SIZE];
+        let mut data = vec![];
+        // The handshake may have read some data along with it.
+        let mut closed = take_plaintext(&mut self.conn.lock().unwrap(), &mut data)?;
+        loop {
+            (&self.plaintext).write_all(&data)?;
+            if closed {
+                return Ok(());
+            }
+
+            let n = (&self.stream).read(&mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            let mut conn = self.conn.lock().unwrap();
+            let mut records = &buf[..n];
+            data.clear();
+            while !records.is_empty() && !closed {
+                conn.read_tls(&mut records)?;
+                if let Err(e) = conn.process_new_packets() {
+                    // Let the client know what went wrong.
+                    let _ = self.flush(conn);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                closed = take_plaintext(&mut conn, &mut data)?;
+            }
+            // Usually nothing, but the client might want a key update.
+            self.flush(conn)?;
+        }
+    }
+
+    // Relays from the handler to the client.
+    fn encrypt_outgoing(&self) {
+        if let Err(e) = self.relay_outgoing() {
+            debug!("TLS connection failed: {}", e);
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn relay_outgoing(&self) -> io::Result<()> {
+        let mut buf = [0; RELAY_BUFFER_SIZE];
+        loop {
+            let n = (&self.plaintext).read(&mut buf)?;
+            let mut conn = self.conn.lock().unwrap();
+            if n == 0 {
+                conn.send_close_notify();
+                self.flush(conn)?;
+                return self.stream.shutdown(Shutdown::Write);
+            }
+            let mut data = &buf[..n];
+            loop {
+                let written = conn.writer().write(data)?;
+                data = &data[written..];
+                self.flush(conn)?;
+                if data.is_empty() {
+                    break;
+                }
+                conn = self.conn.lock().unwrap();
+            }
+        }
+    }
+
+    // Sends whatever TLS records `conn` has ready, letting go of it before
+    // waiting on the network.
+    fn flush(&self, mut conn: MutexGuard<ServerConnection>) -> io::Result<()> {
+        let mut records = vec![];
+        while conn.wants_write() {
+            conn.write_tls(&mut records)?;
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut writer = self.writer.lock().unwrap();
+        drop(conn);
+        writer.write_all(&records)
+    }
+}
+
+// Appends everything the client has sent that's been decrypted so far to
+// `data`, and returns whether they've said they're done.
+fn take_plaintext(conn: &mut ServerConnection, data: &mut Vec<u8>) -> io::Result<bool> {
+    match conn.reader().read_to_end(data) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
@@ -1,7 +1,10 @@
+// Not every test binary uses every helper in here.
+#![allow(dead_code)]
+
 use global_counter::global_counter;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::net::{Shutdown, TcpStream};
-use std::process::{Child, Command};
+use std::process::{Child, Command, Output, Stdio};
 use std::thread;
 use std::time::Duration;
 
@@ -13,30 +16,49 @@ pub struct ServerProcess {
 }
 
 enum ServerType {
+    SmokeTest,
     PrimeTime,
     MeansToAnEnd,
 }
 
 impl ServerProcess {
+    pub fn run_smoke_test() -> Self {
+        ServerProcess::run(ServerType::SmokeTest, &[])
+    }
+
+    pub fn run_smoke_test_with_args(server_args: &[&str]) -> Self {
+        ServerProcess::run(ServerType::SmokeTest, server_args)
+    }
+
     pub fn run_prime_time() -> Self {
-        ServerProcess::run(ServerType::PrimeTime)
+        ServerProcess::run(ServerType::PrimeTime, &[])
+    }
+
+    pub fn run_prime_time_with_args(server_args: &[&str]) -> Self {
+        ServerProcess::run(ServerType::PrimeTime, server_args)
     }
 
     pub fn run_means_to_an_end() -> Self {
-        ServerProcess::run(ServerType::MeansToAnEnd)
+        ServerProcess::run(ServerType::MeansToAnEnd, &[])
+    }
+
+    pub fn run_means_to_an_end_with_args(server_args: &[&str]) -> Self {
+        ServerProcess::run(ServerType::MeansToAnEnd, server_args)
     }
 
     // Runs a server process of the given type and waits to be able to connect
     // to it before returning.
-    fn run(server_type: ServerType) -> Self {
+    fn run(server_type: ServerType, server_args: &[&str]) -> Self {
         let port = &(PORT_COUNTER.inc_cloning().to_string());
 
         let mut cargo_args = vec!["run", "--", "-p", port];
         let mut args = match server_type {
-            ServerType::PrimeTime => vec!["prime-time"],
-            ServerType::MeansToAnEnd => vec!["means-to-an-end"],
+            ServerType::SmokeTest => vec!["smoke-test", "server"],
+            ServerType::PrimeTime => vec!["prime-time", "server"],
+            ServerType::MeansToAnEnd => vec!["means-to-an-end", "server"],
         };
         cargo_args.append(&mut args);
+        cargo_args.extend_from_slice(server_args);
 
         let child = Command::new("cargo").args(cargo_args).spawn().unwrap();
 
@@ -79,9 +101,19 @@ impl ServerProcess {
         }
     }
 
-    fn url(&self) -> String {
+    pub fn url(&self) -> String {
         format!("127.0.0.1:{}", self.port)
     }
+
+    // Whether the server's stopped of its own accord.
+    pub fn has_exited(&mut self) -> bool {
+        self.process.try_wait().unwrap().is_some()
+    }
+}
+
+// A port no other server in this test binary will use.
+pub fn next_port() -> String {
+    PORT_COUNTER.inc_cloning().to_string()
 }
 
 impl Drop for ServerProcess {
@@ -97,6 +129,49 @@ impl Drop for ServerProcess {
     }
 }
 
+// Makes an HTTP request of the admin endpoint, returning the status code
+// and body.
+pub fn admin_request(admin: &str, method: &str, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(admin).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        method, path
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+// The value of one of the server's counters, read from its admin endpoint.
+pub fn metric(admin: &str, name: &str) -> u64 {
+    let (_, body) = admin_request(admin, "GET", "/metrics");
+    body.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+        .unwrap_or(0)
+}
+
+// Runs the binary with `args`, e.g. as a client, feeding it `stdin`, and waits
+// for it to finish.
+pub fn run_command(args: &[&str], stdin: &[u8]) -> Output {
+    let mut cargo_args = vec!["run", "-q", "--"];
+    cargo_args.extend_from_slice(args);
+
+    let mut child = Command::new("cargo")
+        .args(cargo_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
 pub fn connection_is_open(conn: &TcpStream) -> bool {
     let mut buf = [0; 10];
     conn.set_read_timeout(Some(Duration::from_millis(100)))
@@ -0,0 +1,175 @@
+mod common;
+
+use protohackers::means_to_an_end::Message;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn run_server_with_admin() -> (common::ServerProcess, String) {
+    let admin_port = common::next_port();
+    let server =
+        common::ServerProcess::run_means_to_an_end_with_args(&["--admin-port", &admin_port]);
+    (server, format!("127.0.0.1:{}", admin_port))
+}
+
+fn insert(stream: &mut TcpStream, timestamp: i32, price: i32) {
+    stream
+        .write_all(&Message::Insert { timestamp, price }.to_frame())
+        .unwrap();
+}
+
+fn query(stream: &mut TcpStream, mintime: i32, maxtime: i32) -> i32 {
+    stream
+        .write_all(&Message::Query { mintime, maxtime }.to_frame())
+        .unwrap();
+    let mut mean = [0; 4];
+    stream.read_exact(&mut mean).unwrap();
+    i32::from_be_bytes(mean)
+}
+
+// The line describing the connection that ends with `description`, once
+// there is one.
+fn find_connection(admin: &str, description: &str) -> String {
+    let start = Instant::now();
+    loop {
+        let (status, body) = common::admin_request(admin, "GET", "/connections");
+        assert_eq!(status, 200);
+        if let Some(line) = body.lines().find(|l| l.ends_with(description)) {
+            return line.to_string();
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "{}", body);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn test_admin_lists_connections() {
+    let (server, admin) = run_server_with_admin();
+
+    let mut stream = server.get_stream();
+    for timestamp in 0..3 {
+        insert(&mut stream, timestamp, 100);
+    }
+    assert_eq!(query(&mut stream, 0, 10), 100);
+
+    let (_, body) = common::admin_request(&admin, "GET", "/connections");
+    assert!(
+        body.starts_with("service=means-to-an-end connections="),
+        "{}",
+        body
+    );
+    // The mean reaches us before the server's counted it as sent, so wait.
+    let line = find_connection(&admin, "received=36 sent=4 state=3 prices stored");
+    assert!(line.contains("client=127.0.0.1:"), "{}", line);
+
+    let (status, body) = common::admin_request(&admin, "GET", "/metrics");
+    assert_eq!(status, 200, "{}", body);
+}
+
+#[test]
+fn test_admin_kills_connection() {
+    let (server, admin) = run_server_with_admin();
+
+    let mut stream = server.get_stream();
+    insert(&mut stream, 1, 1);
+    assert_eq!(query(&mut stream, 0, 10), 1);
+    let line = find_connection(&admin, "state=1 prices stored");
+    let id = line.strip_prefix("id=").unwrap().split(' ').next().unwrap();
+
+    let (status, body) =
+        common::admin_request(&admin, "POST", &format!("/connections/{}/kill", id));
+    assert_eq!(status, 200, "{}", body);
+    let mut buf = [0; 1];
+    assert!(matches!(stream.read(&mut buf), Ok(0) | Err(_)));
+
+    let (status, _) = common::admin_request(&admin, "POST", "/connections/999999/kill");
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn test_admin_log_level() {
+    let (_server, admin) = run_server_with_admin();
+
+    assert_eq!(
+        common::admin_request(&admin, "POST", "/log-level/debug"),
+        (200, "Log level is DEBUG.\n".to_string())
+    );
+    assert_eq!(
+        common::admin_request(&admin, "POST", "/log-level/default").0,
+        200
+    );
+    assert_eq!(
+        common::admin_request(&admin, "POST", "/log-level/loud").0,
+        400
+    );
+    assert_eq!(common::admin_request(&admin, "GET", "/nowhere").0, 404);
+}
+
+#[test]
+fn test_admin_drain() {
+    let (mut server, admin) = run_server_with_admin();
+
+    let mut stream = server.get_stream();
+    insert(&mut stream, 1, 5);
+    let (status, body) = common::admin_request(&admin, "POST", "/drain");
+    assert_eq!(status, 202, "{}", body);
+
+    // No one new gets in...
+    let start = Instant::now();
+    while TcpStream::connect(server.url()).is_ok() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(100));
+    }
+    // ...but the connections already open carry on.
+    assert_eq!(query(&mut stream, 0, 10), 5);
+    assert!(!server.has_exited());
+
+    drop(stream);
+    let start = Instant::now();
+    while !server.has_exited() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn test_admin_drain_flushes_relays() {
+    let admin_port = common::next_port();
+    // Throttling puts a relay between the handler and the client.
+    let mut server = common::ServerProcess::run_smoke_test_with_args(&[
+        "--admin-port",
+        &admin_port,
+        "--bytes-per-second",
+        "1000000000",
+    ]);
+    let admin = format!("127.0.0.1:{}", admin_port);
+
+    // Once something's echoed, the server's taken the connection on.
+    let mut stream = server.get_stream();
+    stream.write_all(b"x").unwrap();
+    stream.read_exact(&mut [0]).unwrap();
+    let (status, body) = common::admin_request(&admin, "POST", "/drain");
+    assert_eq!(status, 202, "{}", body);
+
+    let payload: Vec<u8> = (0..4_000_000).map(|i| (i % 251) as u8).collect();
+    let mut writer = stream.try_clone().unwrap();
+    let sent = payload.clone();
+    let sender = thread::spawn(move || {
+        writer.write_all(&sent).unwrap();
+        writer.shutdown(Shutdown::Write).unwrap();
+    });
+    // Fall behind, so the echo's still on its way when the handler's done.
+    thread::sleep(Duration::from_secs(1));
+    let mut echo = vec![];
+    (&stream).read_to_end(&mut echo).unwrap();
+    sender.join().unwrap();
+    assert_eq!(echo.len(), payload.len());
+    assert!(echo == payload);
+
+    let start = Instant::now();
+    while !server.has_exited() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+#[test]
+fn test_check_each_service() {
+    for (service, server) in [
+        ("smoke-test", common::ServerProcess::run_smoke_test()),
+        ("prime-time", common::ServerProcess::run_prime_time()),
+        (
+            "means-to-an-end",
+            common::ServerProcess::run_means_to_an_end(),
+        ),
+    ] {
+        let output = common::run_command(&["check", service, &server.url()], b"");
+        let report = String::from_utf8(output.stdout).unwrap();
+        assert!(output.status.success(), "{}", report);
+        assert!(report.contains("PASS "), "{}", report);
+        assert!(!report.contains("FAIL "), "{}", report);
+    }
+}
+
+#[test]
+fn test_check_wrong_service() {
+    // An echo server is no good at primality testing.
+    let server = common::ServerProcess::run_smoke_test();
+    let output = common::run_command(&["check", "prime-time", &server.url()], b"");
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(!output.status.success(), "{}", report);
+    assert!(
+        report.contains("FAIL answers conforming requests"),
+        "{}",
+        report
+    );
+}
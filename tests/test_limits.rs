@@ -0,0 +1,114 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Whether the server turned the connection away rather than serving it.
+fn is_rejected(server: &common::ServerProcess) -> bool {
+    let mut stream = server.get_stream();
+    let _ = stream.write_all(b"Hello");
+    let _ = stream.shutdown(Shutdown::Write);
+    let mut echo = vec![];
+    match stream.read_to_end(&mut echo) {
+        Ok(_) => echo.is_empty(),
+        Err(_) => true,
+    }
+}
+
+#[test]
+fn test_max_connections_per_ip() {
+    let server =
+        common::ServerProcess::run_smoke_test_with_args(&["--max-connections-per-ip", "2"]);
+    // Starting the server took one connection, so wait until it's been let go
+    // and we can hold two open at once.
+    let start = Instant::now();
+    let (first, second) = loop {
+        let first = server.get_stream();
+        let second = server.get_stream();
+        if common::connection_is_open(&first) && common::connection_is_open(&second) {
+            break (first, second);
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert!(is_rejected(&server));
+
+    drop(first);
+    drop(second);
+    let start = Instant::now();
+    while is_rejected(&server) {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn test_connections_per_second() {
+    let server =
+        common::ServerProcess::run_smoke_test_with_args(&["--connections-per-second", "0.5"]);
+    // Let the bucket refill after the connection that checked the server had
+    // started.
+    thread::sleep(Duration::from_secs(2));
+
+    assert!(!is_rejected(&server));
+    assert!(is_rejected(&server));
+}
+
+#[test]
+fn test_allowlist() {
+    let server = common::ServerProcess::run_smoke_test_with_args(&[
+        "--max-connections-per-ip",
+        "1",
+        "--allow-ip",
+        "127.0.0.1",
+    ]);
+
+    let streams: Vec<TcpStream> = (0..3).map(|_| server.get_stream()).collect();
+    assert!(!is_rejected(&server));
+    drop(streams);
+}
+
+#[test]
+fn test_bytes_per_second() {
+    let server = common::ServerProcess::run_smoke_test_with_args(&["--bytes-per-second", "10000"]);
+
+    // The first second's worth goes straight through, the rest takes 2s.
+    let payload = vec![b'x'; 30_000];
+    let start = Instant::now();
+    assert_eq!(server.send_request(&payload), payload);
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+}
+
+#[test]
+fn test_messages_per_second() {
+    let server = common::ServerProcess::run_prime_time_with_args(&["--messages-per-second", "10"]);
+
+    let requests: String = (0..30)
+        .map(|n| format!("{{\"method\":\"isPrime\",\"number\":{}}}\n", n))
+        .collect();
+    let start = Instant::now();
+    let responses = server.send_request(requests.as_bytes());
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+    assert_eq!(responses.iter().filter(|&&b| b == b'\n').count(), 30);
+}
+
+#[test]
+fn test_rejects_bad_rates() {
+    for flag in [
+        "--connections-per-second",
+        "--bytes-per-second",
+        "--messages-per-second",
+    ] {
+        for rate in ["-1", "0", "nan", "inf", "fast"] {
+            // With `=`, so clap doesn't take "-1" for a flag.
+            let rate_arg = format!("{}={}", flag, rate);
+            let output = common::run_command(&["smoke-test", "server", &rate_arg], b"");
+            assert!(!output.status.success(), "{}", rate_arg);
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains(flag), "{}: {}", rate_arg, stderr);
+            assert!(!stderr.contains("panicked"), "{}: {}", rate_arg, stderr);
+        }
+    }
+}
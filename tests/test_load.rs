@@ -0,0 +1,95 @@
+mod common;
+
+// Runs the load generator and returns its report.
+fn run_load(args: &[&str]) -> String {
+    let mut load_args = vec!["load"];
+    load_args.extend_from_slice(args);
+    let output = common::run_command(&load_args, b"");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_load_each_service() {
+    for (service, server) in [
+        ("smoke-test", common::ServerProcess::run_smoke_test()),
+        ("prime-time", common::ServerProcess::run_prime_time()),
+        (
+            "means-to-an-end",
+            common::ServerProcess::run_means_to_an_end(),
+        ),
+    ] {
+        let report = run_load(&[
+            service,
+            "--addr",
+            &server.url(),
+            "--clients",
+            "4",
+            "--requests",
+            "50",
+        ]);
+        assert!(report.starts_with("200 requests, 0 errors"), "{}", report);
+        assert!(report.contains("p99="), "{}", report);
+    }
+}
+
+#[test]
+fn test_load_counts_errors() {
+    // The echo server sends Prime Time requests straight back.
+    let server = common::ServerProcess::run_smoke_test();
+
+    let report = run_load(&[
+        "prime-time",
+        "--addr",
+        &server.url(),
+        "--clients",
+        "2",
+        "--requests",
+        "3",
+    ]);
+    assert!(report.starts_with("0 requests, 6 errors"), "{}", report);
+}
+
+#[test]
+fn test_load_counts_failed_connections_as_errors() {
+    // Nothing's listening here.
+    let address = format!("127.0.0.1:{}", common::next_port());
+
+    let report = run_load(&[
+        "smoke-test",
+        "--addr",
+        &address,
+        "--clients",
+        "1",
+        "--requests",
+        "2",
+    ]);
+    assert!(report.starts_with("0 requests, 2 errors"), "{}", report);
+    assert!(report.contains("(0 requests/s)"), "{}", report);
+}
+
+#[test]
+fn test_load_for_duration() {
+    let server = common::ServerProcess::run_smoke_test();
+
+    let start = std::time::Instant::now();
+    let report = run_load(&["smoke-test", "--addr", &server.url(), "--duration", "0.5"]);
+    assert!(report.contains(" 0 errors"), "{}", report);
+    assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+}
+
+#[test]
+fn test_load_rejects_bad_durations() {
+    for duration in ["-1", "0", "nan", "inf", "1e300", "soon"] {
+        // With `=`, so clap doesn't take "-1" for a flag.
+        let duration_arg = format!("--duration={}", duration);
+        let output = common::run_command(
+            &["load", "smoke-test", &duration_arg, "--requests", "1"],
+            b"",
+        );
+        assert!(!output.status.success(), "{}", duration);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--duration"), "{}: {}", duration, stderr);
+        assert!(!stderr.contains("panicked"), "{}: {}", duration, stderr);
+    }
+}
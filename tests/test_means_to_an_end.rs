@@ -1,6 +1,8 @@
 use protohackers::means_to_an_end::Message;
 use std::io::{Read, Write};
 use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
 
 mod common;
 
@@ -209,16 +211,463 @@ fn test_mutiple_clients() {
 }
 
 fn insert(stream: &mut TcpStream, timestamp: i32, price: i32) {
-    let message = Message::Insert { timestamp, price };
-    stream.write_all(&message.to_network_bytes()).unwrap();
+    send(stream, Message::Insert { timestamp, price });
+}
+
+fn send(stream: &mut TcpStream, message: Message) {
+    stream.write_all(&message.to_frame()).unwrap();
 }
 
 fn query(stream: &mut TcpStream, mintime: i32, maxtime: i32) -> i32 {
     let message = Message::Query { mintime, maxtime };
-    stream.write_all(&message.to_network_bytes()).unwrap();
+    stream.write_all(&message.to_frame()).unwrap();
+
+    read_mean(stream)
+}
 
+fn read_mean(stream: &mut TcpStream) -> i32 {
     let mut buf: [u8; 4] = [0; 4];
     stream.read_exact(&mut buf).unwrap();
 
     i32::from_be_bytes(buf)
 }
+
+#[test]
+fn test_entries_per_session_limit_rejects_inserts() {
+    let server =
+        common::ServerProcess::run_means_to_an_end_with_args(&["--max-entries-per-session", "2"]);
+
+    let mut stream = server.get_stream();
+    insert(&mut stream, 1000, 100);
+    insert(&mut stream, 1010, 200);
+    insert(&mut stream, 1020, 900); // Rejected.
+
+    assert_eq!(query(&mut stream, 0, 2000), 150);
+
+    // Overwriting an existing timestamp doesn't count against the limit.
+    insert(&mut stream, 1010, 300);
+    assert_eq!(query(&mut stream, 0, 2000), 200);
+
+    // Other sessions get their own allowance.
+    let mut other_stream = server.get_stream();
+    insert(&mut other_stream, 1000, 10);
+    insert(&mut other_stream, 1010, 20);
+    assert_eq!(query(&mut other_stream, 0, 2000), 15);
+}
+
+#[test]
+fn test_entries_per_session_limit_evicts_oldest() {
+    let server = common::ServerProcess::run_means_to_an_end_with_args(&[
+        "--max-entries-per-session",
+        "2",
+        "--limit-policy",
+        "evict-oldest",
+    ]);
+
+    let mut stream = server.get_stream();
+    insert(&mut stream, 1020, 100);
+    insert(&mut stream, 1010, 200);
+    insert(&mut stream, 1000, 900); // Evicts the price at 1020.
+
+    assert_eq!(query(&mut stream, 0, 2000), 550);
+    assert_eq!(query(&mut stream, 1020, 1020), 0);
+}
+
+#[test]
+fn test_entries_per_session_limit_disconnects() {
+    let server = common::ServerProcess::run_means_to_an_end_with_args(&[
+        "--max-entries-per-session",
+        "1",
+        "--limit-policy",
+        "disconnect",
+    ]);
+
+    let mut stream = server.get_stream();
+    insert(&mut stream, 1000, 100);
+    assert_eq!(query(&mut stream, 0, 2000), 100);
+    assert!(common::connection_is_open(&stream));
+
+    insert(&mut stream, 1010, 200);
+    assert!(!common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_total_memory_limit_is_shared_across_sessions() {
+    // Each price takes up 8 bytes, so this allows three across all sessions.
+    let server =
+        common::ServerProcess::run_means_to_an_end_with_args(&["--max-total-memory", "24"]);
+
+    let mut stream1 = server.get_stream();
+    insert(&mut stream1, 1000, 100);
+    insert(&mut stream1, 1010, 200);
+
+    let mut stream2 = server.get_stream();
+    insert(&mut stream2, 1000, 300);
+    insert(&mut stream2, 1010, 400); // Rejected.
+    assert_eq!(query(&mut stream2, 0, 2000), 300);
+
+    // Closing a session frees up its memory for everyone else.
+    drop(stream1);
+    thread::sleep(Duration::from_millis(200));
+
+    insert(&mut stream2, 1010, 400);
+    assert_eq!(query(&mut stream2, 0, 2000), 350);
+}
+
+#[test]
+fn test_pipelined_and_split_frames() {
+    let server = common::ServerProcess::run_means_to_an_end();
+    let mut stream = server.get_stream();
+
+    // Many frames in a single write, with answers expected in order.
+    let mut bytes = vec![];
+    for (timestamp, price) in [(1000, 100), (1010, 200), (1020, 300)] {
+        bytes.extend(Message::Insert { timestamp, price }.to_frame());
+    }
+    for (mintime, maxtime) in [(0, 2000), (1000, 1010), (1020, 1020)] {
+        bytes.extend(Message::Query { mintime, maxtime }.to_frame());
+    }
+    stream.write_all(&bytes).unwrap();
+    assert_eq!(read_mean(&mut stream), 200);
+    assert_eq!(read_mean(&mut stream), 150);
+    assert_eq!(read_mean(&mut stream), 300);
+
+    // A frame split across several writes.
+    let frame = Message::Query {
+        mintime: 0,
+        maxtime: 2000,
+    }
+    .to_frame();
+    for chunk in frame.chunks(2) {
+        stream.write_all(chunk).unwrap();
+        stream.flush().unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(read_mean(&mut stream), 200);
+}
+
+#[test]
+fn test_resync_after_garbage() {
+    let server =
+        common::ServerProcess::run_means_to_an_end_with_args(&["--garbage-policy", "resync"]);
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1000, 100);
+    stream.write_all(b"xyz").unwrap();
+    insert(&mut stream, 1010, 200);
+
+    assert_eq!(query(&mut stream, 0, 2000), 150);
+}
+
+#[test]
+fn test_invalid_message_is_ignored_by_default() {
+    let server = common::ServerProcess::run_means_to_an_end();
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1000, 100);
+    stream.write_all(b"X12345678").unwrap();
+    assert_eq!(query(&mut stream, 0, 2000), 100);
+}
+
+#[test]
+fn test_invalid_message_disconnects() {
+    let server = common::ServerProcess::run_means_to_an_end_with_args(&[
+        "--invalid-message-policy",
+        "disconnect",
+    ]);
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1000, 100);
+    assert_eq!(query(&mut stream, 0, 2000), 100);
+
+    stream.write_all(b"X12345678").unwrap();
+    assert!(!common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_invalid_message_gets_error_frame() {
+    let server = common::ServerProcess::run_means_to_an_end_with_args(&[
+        "--invalid-message-policy",
+        "error-frame",
+    ]);
+    let mut stream = server.get_stream();
+
+    stream.write_all(b"X12345678").unwrap();
+    let mut buf = [0; 9];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[0], b'E');
+    assert_eq!(i32::from_be_bytes(buf[1..5].try_into().unwrap()), 1);
+    assert_eq!(
+        i32::from_be_bytes(buf[5..9].try_into().unwrap()),
+        b'X' as i32
+    );
+
+    // The connection carries on as normal afterwards.
+    insert(&mut stream, 1000, 100);
+    assert_eq!(query(&mut stream, 0, 2000), 100);
+}
+
+#[test]
+fn test_candles() {
+    let server = common::ServerProcess::run_means_to_an_end();
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1005, 100);
+    insert(&mut stream, 1000, 110);
+    insert(&mut stream, 1009, 90);
+    insert(&mut stream, 1002, 130);
+    // Nothing between 1010 and 1019.
+    insert(&mut stream, 1025, 200);
+    insert(&mut stream, 1020, 210);
+    insert(&mut stream, 1040, 500); // Outside the range.
+
+    // Buckets start at mintime: [1000, 1009], [1010, 1019], [1020, 1029], ...
+    assert_eq!(
+        candles(&mut stream, 1000, 1035, 10),
+        vec![
+            // start, open, high, low, close, mean, count
+            [1000, 110, 130, 90, 90, 107, 4],
+            [1020, 210, 210, 200, 200, 205, 2],
+        ]
+    );
+
+    // The plain mean is unaffected.
+    assert_eq!(query(&mut stream, 1000, 1035), 140);
+
+    // Degenerate queries return no candles.
+    assert_eq!(candles(&mut stream, 2000, 1000, 10), Vec::<[i32; 7]>::new());
+    assert_eq!(candles(&mut stream, 1000, 2000, 0), Vec::<[i32; 7]>::new());
+    assert_eq!(candles(&mut stream, 5000, 6000, 10), Vec::<[i32; 7]>::new());
+}
+
+fn candles(stream: &mut TcpStream, mintime: i32, maxtime: i32, width: i32) -> Vec<[i32; 7]> {
+    stream
+        .write_all(
+            &Message::Candles {
+                mintime,
+                maxtime,
+                width,
+            }
+            .to_frame(),
+        )
+        .unwrap();
+
+    let count = read_mean(stream);
+    (0..count)
+        .map(|_| {
+            let mut candle = [0; 7];
+            for field in candle.iter_mut() {
+                *field = read_mean(stream);
+            }
+            candle
+        })
+        .collect()
+}
+
+#[test]
+fn test_delete_and_delete_range() {
+    let server = common::ServerProcess::run_means_to_an_end();
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1000, 100);
+    insert(&mut stream, 1010, 200);
+    insert(&mut stream, 1020, 300);
+    insert(&mut stream, 1030, 400);
+    insert(&mut stream, 1040, 500);
+
+    send(&mut stream, Message::Delete { timestamp: 1010 });
+    assert_eq!(query(&mut stream, 0, 2000), 325);
+    assert_eq!(query(&mut stream, 1010, 1010), 0);
+
+    // Deleting something that isn't there is a no-op.
+    send(&mut stream, Message::Delete { timestamp: 1015 });
+    assert_eq!(query(&mut stream, 0, 2000), 325);
+
+    // The range is inclusive.
+    send(
+        &mut stream,
+        Message::DeleteRange {
+            mintime: 1020,
+            maxtime: 1040,
+        },
+    );
+    assert_eq!(query(&mut stream, 0, 2000), 100);
+
+    // A range with min greater than max deletes nothing.
+    send(
+        &mut stream,
+        Message::DeleteRange {
+            mintime: 2000,
+            maxtime: 0,
+        },
+    );
+    assert_eq!(query(&mut stream, 0, 2000), 100);
+}
+
+#[test]
+fn test_deletes_free_up_room() {
+    let server =
+        common::ServerProcess::run_means_to_an_end_with_args(&["--max-entries-per-session", "1"]);
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1000, 100);
+    send(&mut stream, Message::Delete { timestamp: 1000 });
+    insert(&mut stream, 1010, 200);
+    assert_eq!(query(&mut stream, 0, 2000), 200);
+}
+
+#[test]
+fn test_insert_if_absent() {
+    let server = common::ServerProcess::run_means_to_an_end();
+    let mut stream = server.get_stream();
+
+    send(
+        &mut stream,
+        Message::InsertIfAbsent {
+            timestamp: 1000,
+            price: 100,
+        },
+    );
+    send(
+        &mut stream,
+        Message::InsertIfAbsent {
+            timestamp: 1000,
+            price: 200,
+        },
+    );
+    assert_eq!(query(&mut stream, 1000, 1000), 100);
+
+    // Plain inserts still overwrite by default.
+    insert(&mut stream, 1000, 300);
+    assert_eq!(query(&mut stream, 1000, 1000), 300);
+}
+
+#[test]
+fn test_duplicate_policy_keep_first() {
+    let server =
+        common::ServerProcess::run_means_to_an_end_with_args(&["--duplicate-policy", "keep-first"]);
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1000, 100);
+    insert(&mut stream, 1000, 200);
+    assert_eq!(query(&mut stream, 1000, 1000), 100);
+}
+
+#[test]
+fn test_duplicate_policy_reject() {
+    let server =
+        common::ServerProcess::run_means_to_an_end_with_args(&["--duplicate-policy", "reject"]);
+    let mut stream = server.get_stream();
+
+    insert(&mut stream, 1000, 100);
+    insert(&mut stream, 1000, 200);
+    assert_eq!(query(&mut stream, 1000, 1000), 100);
+
+    // Rejected duplicates don't end the session.
+    insert(&mut stream, 1010, 300);
+    assert_eq!(query(&mut stream, 0, 2000), 200);
+}
+
+#[test]
+fn test_client() {
+    let server = common::ServerProcess::run_means_to_an_end();
+
+    let output = common::run_command(
+        &[
+            "means-to-an-end",
+            "client",
+            "--addr",
+            &server.url(),
+            "insert 12345 101",
+            "insert 12346 102",
+            "insert 12347 100",
+            "insert 40960 5",
+            "query 12288 16384",
+            "query 0 1",
+        ],
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "101\n0\n");
+
+    let output = common::run_command(
+        &[
+            "means-to-an-end",
+            "client",
+            "--addr",
+            &server.url(),
+            "insert 1",
+        ],
+        b"",
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Can't make sense of 'insert 1'"));
+}
+
+#[test]
+fn test_client_script() {
+    let server = common::ServerProcess::run_means_to_an_end();
+
+    let path = std::env::temp_dir().join(format!("means-to-an-end-script-{}", std::process::id()));
+    std::fs::write(
+        &path,
+        "# Prices for a few seconds.\n\
+         insert 0 10\n\
+         insert 1 30\n\
+         insert 10 20\n\
+         \n\
+         query 0 10\n\
+         candles 0 19 10\n\
+         delete 10\n\
+         candles 0 19 10\n\
+         delete-range 0 1\n\
+         candles 0 19 10\n\
+         quit\n\
+         query 0 10\n",
+    )
+    .unwrap();
+    let output = common::run_command(
+        &[
+            "means-to-an-end",
+            "client",
+            "--addr",
+            &server.url(),
+            "--script",
+            path.to_str().unwrap(),
+        ],
+        b"",
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "20\n\
+         start=0 open=10 high=30 low=10 close=30 mean=20 count=2\n\
+         start=10 open=20 high=20 low=20 close=20 mean=20 count=1\n\
+         start=0 open=10 high=30 low=10 close=30 mean=20 count=2\n\
+         no candles\n"
+    );
+}
+
+#[test]
+fn test_client_reads_commands_from_stdin() {
+    let server = common::ServerProcess::run_means_to_an_end();
+
+    let output = common::run_command(
+        &["means-to-an-end", "client", "--addr", &server.url()],
+        b"insert 5 50\nquery 0 10\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"50\n");
+
+    // Piped input is a script, so a bad command stops it.
+    let output = common::run_command(
+        &["means-to-an-end", "client", "--addr", &server.url()],
+        b"insert 5 50\nqueery 0 10\nquery 0 10\n",
+    );
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Line 2: Can't make sense of 'queery 0 10'"));
+}
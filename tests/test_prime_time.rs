@@ -1,4 +1,6 @@
 use json::object;
+use std::io::{Read, Write};
+use std::net::TcpStream;
 
 mod common;
 
@@ -115,3 +117,809 @@ fn test_with_incorrect_method() {
     assert_eq!(response, "ERROR");
     assert!(!common::connection_is_open(&stream));
 }
+
+#[test]
+fn test_with_integers_bigger_than_u64() {
+    let server = common::ServerProcess::run_prime_time();
+    let mut stream = server.get_stream();
+
+    // 2^127 - 1 is prime, 2^64 + 1 isn't.
+    for (number, prime) in [
+        ("170141183460469231731687303715884105727", true),
+        ("18446744073709551617", false),
+        ("-170141183460469231731687303715884105727", false),
+        ("170141183460469231731687303715884105727.5", false),
+        (
+            "1000000000000000000000000000000000000000000000000000000000000000000000001",
+            false,
+        ),
+    ] {
+        common::write_line(
+            &mut stream,
+            format!(r#"{{"method":"isPrime","number":{}}}"#, number),
+        );
+        let response = common::read_line(&mut stream);
+        let response = json::parse(&response).unwrap();
+
+        assert_eq!(
+            response,
+            object! {method: "isPrime", prime: prime},
+            "{}",
+            number
+        );
+    }
+    assert!(common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_with_integers_too_long_to_test() {
+    let server = common::ServerProcess::run_prime_time();
+
+    // However long they are, numbers with a small factor are answered.
+    let two_then_zeros = format!("2{}", "0".repeat(1500));
+    let ten_to_the_1000_plus_1 = format!("1{}1", "0".repeat(999));
+    for number in [two_then_zeros.as_str(), &ten_to_the_1000_plus_1, "2e1500"] {
+        for method in ["isPrime", "isProbablePrime"] {
+            let mut stream = server.get_stream();
+            common::write_line(
+                &mut stream,
+                format!(r#"{{"method":"{}","number":{}}}"#, method, number),
+            );
+            let response = json::parse(&common::read_line(&mut stream)).unwrap();
+            let expected = object! {method: method, prime: false};
+            assert_eq!(response, expected, "{}", number);
+        }
+    }
+    // Too big to write out, but a multiple of ten all the same.
+    let mut stream = server.get_stream();
+    common::write_line(
+        &mut stream,
+        r#"{"method":"isPrime","number":1e5000}"#.into(),
+    );
+    let response = json::parse(&common::read_line(&mut stream)).unwrap();
+    assert_eq!(response, object! {method: "isPrime", prime: false});
+
+    // A thousand digits is as long as we'll test anything else.
+    for method in ["isPrime", "isProbablePrime"] {
+        let mut stream = server.get_stream();
+        common::write_line(
+            &mut stream,
+            format!(
+                r#"{{"method":"{}","number":1{}9}}"#,
+                method,
+                "0".repeat(999)
+            ),
+        );
+        assert_eq!(common::read_line(&mut stream), "ERROR", "{}", method);
+        assert!(!common::connection_is_open(&stream));
+    }
+}
+
+#[test]
+fn test_max_digits() {
+    let server = common::ServerProcess::run_prime_time_with_args(&["--max-digits", "5"]);
+
+    let mut stream = server.get_stream();
+    common::write_line(
+        &mut stream,
+        r#"{"method":"isPrime","number":1000002}"#.into(),
+    );
+    let response = json::parse(&common::read_line(&mut stream)).unwrap();
+    assert_eq!(response, object! {method: "isPrime", prime: false});
+
+    // 1000003 is prime, but too long to find out.
+    common::write_line(
+        &mut stream,
+        r#"{"method":"isPrime","number":1000003}"#.into(),
+    );
+    assert_eq!(common::read_line(&mut stream), "ERROR");
+}
+
+#[test]
+fn test_other_methods() {
+    let server = common::ServerProcess::run_prime_time();
+    let mut stream = server.get_stream();
+
+    let cases = [
+        (
+            object! {method: "factorize", number: 360},
+            object! {method: "factorize", factors: [2, 2, 2, 3, 3, 5]},
+        ),
+        (
+            object! {method: "factorize", number: 1},
+            object! {method: "factorize", factors: []},
+        ),
+        (
+            object! {method: "nextPrime", number: 97},
+            object! {method: "nextPrime", prime: 101},
+        ),
+        (
+            object! {method: "nextPrime", number: 0},
+            object! {method: "nextPrime", prime: 2},
+        ),
+        (
+            object! {method: "primeCount", number: 1000},
+            object! {method: "primeCount", count: 168},
+        ),
+        (
+            object! {method: "nthPrime", number: 1000},
+            object! {method: "nthPrime", prime: 7919},
+        ),
+        (
+            object! {method: "isProbablePrime", number: 7919, confidence: 10},
+            object! {method: "isProbablePrime", prime: true},
+        ),
+        (
+            object! {method: "isProbablePrime", number: 7917},
+            object! {method: "isProbablePrime", prime: false},
+        ),
+    ];
+
+    for (request, expected) in cases {
+        common::write_json_line(&mut stream, &request);
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+        assert_eq!(response, expected, "{}", request.dump());
+    }
+    assert!(common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_other_methods_reject_invalid_arguments() {
+    let server = common::ServerProcess::run_prime_time();
+
+    let requests = [
+        object! {method: "factorize", number: 3.5},
+        object! {method: "factorize", number: -4},
+        object! {method: "factorize", number: "12"},
+        object! {method: "factorize"},
+        object! {method: "nthPrime", number: 0},
+        object! {method: "primeCount", number: 1e12},
+        object! {method: "isProbablePrime", number: 7919, confidence: 0},
+        object! {method: "isProbablePrime", number: 7919, confidence: 1.5},
+    ];
+
+    for request in requests {
+        let mut stream = server.get_stream();
+        common::write_json_line(&mut stream, &request);
+        let response = common::read_line(&mut stream);
+
+        assert_eq!(response, "ERROR", "{}", request.dump());
+        assert!(!common::connection_is_open(&stream));
+    }
+}
+
+#[test]
+fn test_json_rpc() {
+    let server = common::ServerProcess::run_prime_time_with_args(&["--protocol", "json-rpc"]);
+    let mut stream = server.get_stream();
+
+    let cases = [
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 97}, "id": 1}"#,
+            object! {jsonrpc: "2.0", result: {method: "isPrime", prime: true}, id: 1},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 170141183460469231731687303715884105727}, "id": "big"}"#,
+            object! {jsonrpc: "2.0", result: {method: "isPrime", prime: true}, id: "big"},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isNotPrime", "params": {"number": 97}, "id": 2}"#,
+            object! {jsonrpc: "2.0", error: {code: -32601, message: "Method not found"}, id: 2},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": "97"}, "id": 3}"#,
+            object! {jsonrpc: "2.0", error: {code: -32602, message: "Invalid params"}, id: 3},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": [97], "id": 4}"#,
+            object! {jsonrpc: "2.0", error: {code: -32602, message: "Invalid params"}, id: 4},
+        ),
+        (
+            r#"{"method": "isPrime", "params": {"number": 97}, "id": 5}"#,
+            object! {jsonrpc: "2.0", error: {code: -32600, message: "Invalid Request"}, id: 5},
+        ),
+        (
+            r#"{ method "isPrime" }"#,
+            object! {jsonrpc: "2.0", error: {code: -32700, message: "Parse error"}, id: null},
+        ),
+        (
+            "[]",
+            object! {jsonrpc: "2.0", error: {code: -32600, message: "Invalid Request"}, id: null},
+        ),
+        (
+            r#"[
+                {"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 4}, "id": 6},
+                {"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 5}},
+                {"jsonrpc": "2.0", "method": "nextPrime", "params": {"number": 5}, "id": 7},
+                1
+            ]"#,
+            json::array![
+                {jsonrpc: "2.0", result: {method: "isPrime", prime: false}, id: 6},
+                {jsonrpc: "2.0", result: {method: "nextPrime", prime: 7}, id: 7},
+                {jsonrpc: "2.0", error: {code: -32600, message: "Invalid Request"}, id: null},
+            ],
+        ),
+    ];
+
+    for (request, expected) in cases {
+        common::write_line(&mut stream, request.replace('\n', " "));
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+        assert_eq!(response, expected, "{}", request);
+    }
+
+    // Notifications don't get a response.
+    common::write_line(
+        &mut stream,
+        r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 97}}"#.to_string(),
+    );
+    common::write_line(
+        &mut stream,
+        r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 98}, "id": 8}"#.to_string(),
+    );
+    let response = json::parse(&common::read_line(&mut stream)).unwrap();
+    assert_eq!(
+        response,
+        object! {jsonrpc: "2.0", result: {method: "isPrime", prime: false}, id: 8}
+    );
+
+    assert!(common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_verbose_errors() {
+    let server = common::ServerProcess::run_prime_time_with_args(&["--verbose-errors"]);
+
+    let cases = [
+        (r#"{ method "isPrime", number: 97}"#, "invalidJson"),
+        (r#"{"number": 97}"#, "missingMethod"),
+        (r#"{"method": "isNotPrime", "number": 97}"#, "unknownMethod"),
+        (r#"{"method": "isPrime"}"#, "missingArgument"),
+        (
+            r#"{"method": "isPrime", "number": "97"}"#,
+            "nonNumericArgument",
+        ),
+        (
+            r#"{"method": "factorize", "number": 9.5}"#,
+            "invalidArgument",
+        ),
+    ];
+
+    for (request, kind) in cases {
+        let mut stream = server.get_stream();
+        common::write_line(&mut stream, request.to_string());
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+
+        assert_eq!(response["error"], kind, "{}", request);
+        assert!(response["message"].is_string());
+        assert!(!common::connection_is_open(&stream));
+    }
+}
+
+#[test]
+fn test_with_oversized_line() {
+    let server = common::ServerProcess::run_prime_time_with_args(&["--max-line-length", "64"]);
+
+    // Lines up to the limit are fine.
+    let mut stream = server.get_stream();
+    let request = format!(r#"{{"method":"isPrime","number":97{}}}"#, " ".repeat(32));
+    assert_eq!(request.len(), 64);
+    common::write_line(&mut stream, request);
+    let response = json::parse(&common::read_line(&mut stream)).unwrap();
+    assert_eq!(response, object! {method: "isPrime", prime: true});
+
+    // Anything longer isn't.
+    let request = format!(r#"{{"method":"isPrime","number":97{}}}"#, " ".repeat(33));
+    common::write_line(&mut stream, request);
+    assert_eq!(common::read_line(&mut stream), "ERROR");
+    assert!(!common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_with_endless_line() {
+    let server = common::ServerProcess::run_prime_time();
+    let mut stream = server.get_stream();
+
+    // Keep sending without a newline. The server should give up once the line
+    // is over the limit instead of buffering it all.
+    let chunk = vec![b' '; 64 * 1024];
+    for _ in 0..32 {
+        if stream.write_all(&chunk).is_err() {
+            break;
+        }
+    }
+
+    // The server closes the connection with our bytes still unread, so the
+    // `ERROR` may be lost to a reset. Either way it mustn't keep waiting.
+    let mut response = vec![];
+    match stream.read_to_end(&mut response) {
+        Ok(_) => assert_eq!(response, b"ERROR"),
+        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+    }
+}
+
+#[test]
+fn test_with_final_line_without_newline() {
+    let server = common::ServerProcess::run_prime_time();
+
+    let response = server.send_request(br#"{"method":"isPrime","number":97}"#);
+    let response = json::parse(std::str::from_utf8(&response).unwrap()).unwrap();
+    assert_eq!(response, object! {method: "isPrime", prime: true});
+}
+
+#[test]
+fn test_pipelined_responses_stay_in_order() {
+    let server = common::ServerProcess::run_prime_time_with_args(&["--max-in-flight", "4"]);
+    let mut stream = server.get_stream();
+
+    // A slow request first, then a pile of quick ones, all in one go.
+    let mut requests = vec![object! {method: "primeCount", number: 100_000_000}];
+    let mut expected = vec![object! {method: "primeCount", count: 5_761_455}];
+    for n in 0..50 {
+        requests.push(object! {method: "isPrime", number: n});
+        expected.push(object! {method: "isPrime", prime: primal::is_prime(n)});
+    }
+    let body: String = requests.iter().map(|r| r.dump() + "\n").collect();
+    stream.write_all(body.as_bytes()).unwrap();
+
+    let mut reader = std::io::BufReader::new(&stream);
+    for e in expected {
+        let mut line = String::new();
+        std::io::BufRead::read_line(&mut reader, &mut line).unwrap();
+        assert_eq!(json::parse(&line).unwrap(), e);
+    }
+    assert!(common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_pipelined_responses_before_malformed_request() {
+    let server = common::ServerProcess::run_prime_time();
+    let mut stream = server.get_stream();
+
+    stream
+        .write_all(b"{\"method\":\"isPrime\",\"number\":7}\n{\"method\":\"isPrime\",\"number\":8}\nnonsense\n{\"method\":\"isPrime\",\"number\":7}\n")
+        .unwrap();
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&response).unwrap(),
+        "{\"method\":\"isPrime\",\"prime\":true}\n{\"method\":\"isPrime\",\"prime\":false}\nERROR"
+    );
+}
+
+#[test]
+fn test_cached_results_shared_across_connections() {
+    // A cache small enough to evict, and a sieve covering only some numbers.
+    let admin_port = common::next_port();
+    let server = common::ServerProcess::run_prime_time_with_args(&[
+        "--cache-capacity",
+        "4",
+        "--sieve-limit",
+        "100",
+        "--admin-port",
+        &admin_port,
+    ]);
+    let admin = format!("127.0.0.1:{}", admin_port);
+
+    let numbers = [
+        ("97", true),
+        ("1009", true),
+        ("1011", false),
+        ("170141183460469231731687303715884105727", true),
+        ("18446744073709551617", false),
+        ("18446744073709551557", true),
+    ];
+    let check = |stream: &mut TcpStream, (number, prime): (&str, bool)| {
+        common::write_line(
+            stream,
+            format!(r#"{{"method":"isPrime","number":{}}}"#, number),
+        );
+        let response = json::parse(&common::read_line(stream)).unwrap();
+        assert_eq!(
+            response,
+            object! {method: "isPrime", prime: prime},
+            "{}",
+            number
+        );
+    };
+    for _ in 0..3 {
+        let mut stream = server.get_stream();
+        for number in numbers {
+            check(&mut stream, number);
+        }
+    }
+
+    // The four numbers asked about last are cached for other connections,
+    // but there was no room left for the one before them.
+    let hits = common::metric(&admin, "prime_time.cache_hits");
+    let misses = common::metric(&admin, "prime_time.cache_misses");
+    let mut stream = server.get_stream();
+    for number in &numbers[2..] {
+        check(&mut stream, *number);
+    }
+    assert_eq!(common::metric(&admin, "prime_time.cache_hits"), hits + 4);
+    assert_eq!(common::metric(&admin, "prime_time.cache_misses"), misses);
+    check(&mut stream, numbers[1]);
+    assert_eq!(
+        common::metric(&admin, "prime_time.cache_misses"),
+        misses + 1
+    );
+}
+
+#[test]
+fn test_numbers_written_in_other_forms() {
+    let server = common::ServerProcess::run_prime_time();
+    let mut stream = server.get_stream();
+
+    for (request, expected) in [
+        (
+            r#"{"method":"isPrime","number":97.0000}"#,
+            object! {method: "isPrime", prime: true},
+        ),
+        (
+            r#"{"method":"isPrime","number":9.7e1}"#,
+            object! {method: "isPrime", prime: true},
+        ),
+        (
+            r#"{"method":"isPrime","number":970E-1}"#,
+            object! {method: "isPrime", prime: true},
+        ),
+        (
+            r#"{"method":"isPrime","number":97.5}"#,
+            object! {method: "isPrime", prime: false},
+        ),
+        (
+            r#"{"method":"isPrime","number":-0}"#,
+            object! {method: "isPrime", prime: false},
+        ),
+        (
+            r#"{"method":"isPrime","number":1e999999}"#,
+            object! {method: "isPrime", prime: false},
+        ),
+        (
+            r#"{"method":"factorize","number":3.6e2}"#,
+            object! {method: "factorize", factors: [2, 2, 2, 3, 3, 5]},
+        ),
+        (
+            r#"{"method":"nextPrime","number":-0.0}"#,
+            object! {method: "nextPrime", prime: 2},
+        ),
+    ] {
+        common::write_line(&mut stream, request.to_string());
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+        assert_eq!(response, expected, "{}", request);
+    }
+    assert!(common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_lenient_validation_ignores_extra_and_duplicate_fields() {
+    let server = common::ServerProcess::run_prime_time();
+    let mut stream = server.get_stream();
+
+    for request in [
+        r#"{"method": "isPrime", "number": 97, "extra": [1, 2]}"#,
+        r#"{"method": "isPrime", "number": 4, "number": 97}"#,
+    ] {
+        common::write_line(&mut stream, request.to_string());
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+        assert_eq!(
+            response,
+            object! {method: "isPrime", prime: true},
+            "{}",
+            request
+        );
+    }
+    assert!(common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_strict_validation() {
+    let server = common::ServerProcess::run_prime_time_with_args(&[
+        "--validation",
+        "strict",
+        "--verbose-errors",
+    ]);
+
+    // Requests with only the fields a method uses are still fine.
+    let mut stream = server.get_stream();
+    for (request, expected) in [
+        (
+            r#"{"method": "isPrime", "number": 97}"#,
+            object! {method: "isPrime", prime: true},
+        ),
+        (
+            r#"{"method": "isProbablePrime", "number": 97, "confidence": 5}"#,
+            object! {method: "isProbablePrime", prime: true},
+        ),
+    ] {
+        common::write_line(&mut stream, request.to_string());
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+        assert_eq!(response, expected, "{}", request);
+    }
+    assert!(common::connection_is_open(&stream));
+
+    for (request, kind) in [
+        (
+            r#"{"method": "isPrime", "number": 97, "extra": 1}"#,
+            "unknownField",
+        ),
+        (
+            r#"{"method": "isPrime", "number": 97, "confidence": 5}"#,
+            "unknownField",
+        ),
+        (
+            r#"{"method": "isPrime", "number": 4, "number": 97}"#,
+            "duplicateField",
+        ),
+        (
+            r#"{"method": "isPrime", "method": "isPrime", "number": 97}"#,
+            "duplicateField",
+        ),
+        (
+            r#"{"method": "isNotPrime", "number": 97, "extra": 1}"#,
+            "unknownMethod",
+        ),
+    ] {
+        let mut stream = server.get_stream();
+        common::write_line(&mut stream, request.to_string());
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+
+        assert_eq!(response["error"], kind, "{}", request);
+        assert!(!common::connection_is_open(&stream));
+    }
+}
+
+#[test]
+fn test_strict_validation_with_json_rpc() {
+    let server = common::ServerProcess::run_prime_time_with_args(&[
+        "--protocol",
+        "json-rpc",
+        "--validation",
+        "strict",
+    ]);
+    let mut stream = server.get_stream();
+
+    for (request, expected) in [
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 97}, "id": 1}"#,
+            object! {jsonrpc: "2.0", result: {method: "isPrime", prime: true}, id: 1},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 97, "extra": 1}, "id": 2}"#,
+            object! {jsonrpc: "2.0", error: {code: -32602, message: "Invalid params"}, id: 2},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 4, "number": 97}, "id": 3}"#,
+            object! {jsonrpc: "2.0", error: {code: -32602, message: "Invalid params"}, id: 3},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 97}, "extra": 1, "id": 4}"#,
+            object! {jsonrpc: "2.0", error: {code: -32600, message: "Invalid Request"}, id: 4},
+        ),
+        (
+            r#"{"jsonrpc": "2.0", "method": "isPrime", "params": {"number": 97}, "id": 5, "id": 6}"#,
+            object! {jsonrpc: "2.0", error: {code: -32600, message: "Invalid Request"}, id: 6},
+        ),
+    ] {
+        common::write_line(&mut stream, request.to_string());
+        let response = json::parse(&common::read_line(&mut stream)).unwrap();
+        assert_eq!(response, expected, "{}", request);
+    }
+    assert!(common::connection_is_open(&stream));
+}
+
+fn write_frame(stream: &mut std::net::TcpStream, request: &rmpv::Value) {
+    let mut body = vec![];
+    rmpv::encode::write_value(&mut body, request).unwrap();
+    stream
+        .write_all(&(body.len() as u32).to_be_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+fn read_frame(stream: &mut std::net::TcpStream) -> rmpv::Value {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut body = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).unwrap();
+    rmpv::decode::read_value(&mut &body[..]).unwrap()
+}
+
+fn request(method: &str, number: rmpv::Value) -> rmpv::Value {
+    rmpv::Value::Map(vec![
+        ("method".into(), method.into()),
+        ("number".into(), number),
+    ])
+}
+
+#[test]
+fn test_binary_transport() {
+    let server = common::ServerProcess::run_prime_time_with_args(&["--binary-transport"]);
+    let mut stream = server.get_stream();
+
+    let cases = [
+        (
+            request("isPrime", 97.into()),
+            rmpv::Value::Map(vec![
+                ("method".into(), "isPrime".into()),
+                ("prime".into(), true.into()),
+            ]),
+        ),
+        (
+            request("isPrime", 97.5.into()),
+            rmpv::Value::Map(vec![
+                ("method".into(), "isPrime".into()),
+                ("prime".into(), false.into()),
+            ]),
+        ),
+        // Floats holding a whole number are integers.
+        (
+            request("isPrime", 97.0.into()),
+            rmpv::Value::Map(vec![
+                ("method".into(), "isPrime".into()),
+                ("prime".into(), true.into()),
+            ]),
+        ),
+        (
+            request("factorize", 12.into()),
+            rmpv::Value::Map(vec![
+                ("method".into(), "factorize".into()),
+                (
+                    "factors".into(),
+                    rmpv::Value::Array(vec![2.into(), 2.into(), 3.into()]),
+                ),
+            ]),
+        ),
+    ];
+    for (request, _) in &cases {
+        write_frame(&mut stream, request);
+    }
+    for (_, expected) in cases {
+        assert_eq!(read_frame(&mut stream), expected);
+    }
+    assert!(common::connection_is_open(&stream));
+
+    // Malformed requests get an error frame and the connection is closed.
+    write_frame(&mut stream, &request("isPrime", "97".into()));
+    let response = read_frame(&mut stream);
+    assert_eq!(response["error"], "nonNumericArgument".into());
+    assert!(!common::connection_is_open(&stream));
+
+    // JSON clients can still use the same server.
+    let mut stream = server.get_stream();
+    common::write_json_line(&mut stream, &object! {method: "isPrime", number: 97});
+    let response = json::parse(&common::read_line(&mut stream)).unwrap();
+    assert_eq!(response, object! {method: "isPrime", prime: true});
+}
+
+#[test]
+fn test_binary_transport_with_json_rpc() {
+    let server = common::ServerProcess::run_prime_time_with_args(&[
+        "--binary-transport",
+        "--protocol",
+        "json-rpc",
+    ]);
+    let mut stream = server.get_stream();
+
+    let call = |method: &str, number: rmpv::Value, id: rmpv::Value| {
+        rmpv::Value::Map(vec![
+            ("jsonrpc".into(), "2.0".into()),
+            ("method".into(), method.into()),
+            (
+                "params".into(),
+                rmpv::Value::Map(vec![("number".into(), number)]),
+            ),
+            ("id".into(), id),
+        ])
+    };
+    let result = |prime: bool, id: rmpv::Value| {
+        rmpv::Value::Map(vec![
+            ("jsonrpc".into(), "2.0".into()),
+            (
+                "result".into(),
+                rmpv::Value::Map(vec![
+                    ("method".into(), "isPrime".into()),
+                    ("prime".into(), prime.into()),
+                ]),
+            ),
+            ("id".into(), id),
+        ])
+    };
+
+    write_frame(&mut stream, &call("isPrime", 97.into(), 1.into()));
+    assert_eq!(read_frame(&mut stream), result(true, 1.into()));
+
+    // A batch, with a notification that gets no response.
+    let batch = rmpv::Value::Array(vec![
+        call("isPrime", 7.into(), "a".into()),
+        rmpv::Value::Map(vec![
+            ("jsonrpc".into(), "2.0".into()),
+            ("method".into(), "isPrime".into()),
+            (
+                "params".into(),
+                rmpv::Value::Map(vec![("number".into(), 8.into())]),
+            ),
+        ]),
+        call("isPrime", 9.5.into(), "b".into()),
+    ]);
+    write_frame(&mut stream, &batch);
+    assert_eq!(
+        read_frame(&mut stream),
+        rmpv::Value::Array(vec![result(true, "a".into()), result(false, "b".into())])
+    );
+
+    write_frame(&mut stream, &call("isPrime", "97".into(), 2.into()));
+    let response = read_frame(&mut stream);
+    assert_eq!(response["error"]["code"], (-32602).into());
+    assert_eq!(response["id"], 2.into());
+    assert!(common::connection_is_open(&stream));
+}
+
+#[test]
+fn test_client() {
+    let server = common::ServerProcess::run_prime_time();
+    let url = server.url();
+
+    let output = common::run_command(
+        &[
+            "prime-time",
+            "client",
+            "--addr",
+            &url,
+            "97",
+            "98",
+            "170141183460469231731687303715884105727",
+        ],
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"method\":\"isPrime\",\"prime\":true}\n\
+         {\"method\":\"isPrime\",\"prime\":false}\n\
+         {\"method\":\"isPrime\",\"prime\":true}\n"
+    );
+
+    let output = common::run_command(
+        &[
+            "prime-time",
+            "client",
+            "--addr",
+            &url,
+            "--method",
+            "nextPrime",
+            "97",
+        ],
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"{\"method\":\"nextPrime\",\"prime\":101}\n");
+
+    // Request lines from stdin.
+    let output = common::run_command(
+        &["prime-time", "client", "--addr", &url],
+        b"{\"method\":\"factorize\",\"number\":12}\n{\"method\":\"isPrime\",\"number\":7}\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"method\":\"factorize\",\"factors\":[2,2,3]}\n{\"method\":\"isPrime\",\"prime\":true}\n"
+    );
+}
+
+#[test]
+fn test_client_fails_on_malformed_request() {
+    let server = common::ServerProcess::run_prime_time();
+
+    let output = common::run_command(
+        &["prime-time", "client", "--addr", &server.url()],
+        b"{\"method\":\"isPrime\",\"number\":7}\nnonsense\n",
+    );
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{\"method\":\"isPrime\",\"prime\":true}\nERROR\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("rejected"));
+}
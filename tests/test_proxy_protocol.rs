@@ -0,0 +1,78 @@
+mod common;
+
+use std::io::{Read, Write};
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[test]
+fn test_proxy_protocol_v1() {
+    let server = common::ServerProcess::run_smoke_test_with_args(&["--proxy-protocol"]);
+
+    let response =
+        server.send_request(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 5001\r\nHello world!");
+    assert_eq!(response, b"Hello world!");
+
+    let response = server.send_request(b"PROXY UNKNOWN\r\nHello again!");
+    assert_eq!(response, b"Hello again!");
+}
+
+#[test]
+fn test_proxy_protocol_v2() {
+    let server = common::ServerProcess::run_smoke_test_with_args(&["--proxy-protocol"]);
+
+    let mut request = V2_SIGNATURE.to_vec();
+    // PROXY command, TCP over IPv4, then the addresses and ports.
+    request.extend([0x21, 0x11, 0x00, 0x0c]);
+    request.extend([192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x13, 0x89]);
+    request.extend(b"Hello world!");
+    assert_eq!(server.send_request(&request), b"Hello world!");
+}
+
+#[test]
+fn test_proxy_protocol_required() {
+    let server = common::ServerProcess::run_smoke_test_with_args(&["--proxy-protocol"]);
+
+    // Without a header, the connection's dropped rather than served.
+    let mut stream = server.get_stream();
+    stream
+        .write_all(b"Hello world! This is not a PROXY header.")
+        .unwrap();
+    let mut response = vec![];
+    let result = stream.read_to_end(&mut response);
+    assert!(result.is_err() || response.is_empty(), "{:?}", response);
+}
+
+#[test]
+fn test_proxy_protocol_silent_connections() {
+    let server = common::ServerProcess::run_smoke_test_with_args(&["--proxy-protocol"]);
+
+    // More connections than there are workers, none of which send a header,
+    // mustn't hold up one that does.
+    let _silent: Vec<_> = (0..10).map(|_| server.get_stream()).collect();
+    // Were it stuck behind them, this would time out before the server gave up.
+    let mut stream = server.get_stream();
+    stream
+        .write_all(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 5001\r\nHello world!")
+        .unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(response, b"Hello world!");
+}
+
+#[test]
+fn test_proxy_protocol_caps_connections_without_headers() {
+    let admin_port = common::next_port();
+    let server = common::ServerProcess::run_smoke_test_with_args(&[
+        "--proxy-protocol",
+        "--admin-port",
+        &admin_port,
+    ]);
+    let admin = format!("127.0.0.1:{}", admin_port);
+
+    // Past as many as we'll wait on for a header, connections are dropped
+    // straight away.
+    let silent: Vec<_> = (0..100).map(|_| server.get_stream()).collect();
+    assert!(!common::connection_is_open(silent.last().unwrap()));
+    assert!(common::metric(&admin, "limits.handshakes_rejected") > 0);
+}
@@ -0,0 +1,138 @@
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::thread;
+
+mod common;
+
+#[test]
+fn test_echo() {
+    let server = common::ServerProcess::run_smoke_test();
+
+    assert_eq!(server.send_request(b"Hello world!"), b"Hello world!");
+    assert_eq!(server.send_request(b""), b"");
+}
+
+#[test]
+fn test_echoes_before_client_finishes_sending() {
+    let server = common::ServerProcess::run_smoke_test();
+    let mut stream = server.get_stream();
+
+    for message in [&b"ping"[..], b"pong", b"\x00\xff binary too"] {
+        stream.write_all(message).unwrap();
+        let mut echoed = vec![0; message.len()];
+        stream.read_exact(&mut echoed).unwrap();
+        assert_eq!(echoed, message);
+    }
+
+    // Once we're done sending, the server finishes too.
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut rest = vec![];
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_echoes_multi_megabyte_payload() {
+    let server = common::ServerProcess::run_smoke_test();
+    let stream = server.get_stream();
+
+    // Enough that neither side could hold it all in socket buffers.
+    let payload: Vec<u8> = (0..16 * 1024 * 1024u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+        .collect();
+
+    // Write and read at the same time, since the server won't read more until
+    // we've taken what it's written back.
+    let mut write_stream = stream.try_clone().unwrap();
+    let to_send = payload.clone();
+    let writer = thread::spawn(move || {
+        for chunk in to_send.chunks(100_000) {
+            write_stream.write_all(chunk).unwrap();
+        }
+        write_stream.shutdown(Shutdown::Write).unwrap();
+    });
+
+    let mut echoed = vec![];
+    (&stream).read_to_end(&mut echoed).unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(echoed.len(), payload.len());
+    assert!(echoed == payload);
+}
+
+#[test]
+fn test_client() {
+    let server = common::ServerProcess::run_smoke_test();
+    let url = server.url();
+
+    let output = common::run_command(
+        &[
+            "smoke-test",
+            "client",
+            "--message",
+            "Hi there",
+            "--addr",
+            &url,
+        ],
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hi there");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Echoed 8 bytes"));
+
+    // From stdin.
+    let payload: Vec<u8> = (0..=255).cycle().take(1_000_000).collect();
+    let output = common::run_command(
+        &["smoke-test", "client", "--input", "-", "--addr", &url],
+        &payload,
+    );
+    assert!(output.status.success());
+    assert!(output.stdout == payload);
+
+    // From a file.
+    let path = std::env::temp_dir().join(format!("smoke-test-payload-{}", std::process::id()));
+    std::fs::write(&path, &payload[..1000]).unwrap();
+    let output = common::run_command(
+        &[
+            "smoke-test",
+            "client",
+            "--input",
+            path.to_str().unwrap(),
+            "--addr",
+            &url,
+        ],
+        b"",
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, &payload[..1000]);
+}
+
+#[test]
+fn test_client_fails_when_echo_differs() {
+    // Prime Time doesn't echo, so this is a handy server that gets it wrong.
+    let server = common::ServerProcess::run_prime_time();
+
+    let output = common::run_command(
+        &[
+            "smoke-test",
+            "client",
+            "--message",
+            "not json\n",
+            "--addr",
+            &server.url(),
+        ],
+        b"",
+    );
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"ERROR");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("differs"));
+}
+
+#[test]
+fn test_client_fails_without_server() {
+    // Nothing's listening on this port.
+    let output = common::run_command(&["smoke-test", "client", "--addr", "127.0.0.1:1"], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Couldn't connect"));
+}
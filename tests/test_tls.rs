@@ -0,0 +1,212 @@
+mod common;
+
+use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A CA, with a server certificate and a client certificate signed by it, all
+// written out as PEM files for the server to read.
+struct Certificates {
+    dir: PathBuf,
+    ca: CertificateDer<'static>,
+    client_cert: CertificateDer<'static>,
+    client_key: Vec<u8>,
+}
+
+impl Certificates {
+    fn generate(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("protohackers-tls-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let mut server_params =
+            CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()]).unwrap();
+        server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        let server_cert = server_params.signed_by(&server_key, &ca, &ca_key).unwrap();
+        fs::write(dir.join("server.pem"), server_cert.pem()).unwrap();
+        fs::write(dir.join("server.key"), server_key.serialize_pem()).unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(vec!["client".to_string()]).unwrap();
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        Certificates {
+            dir,
+            ca: ca.der().clone(),
+            client_cert: client_cert.der().clone(),
+            client_key: client_key.serialize_der(),
+        }
+    }
+
+    fn path(&self, file: &str) -> String {
+        self.dir.join(file).to_str().unwrap().to_string()
+    }
+
+    fn server_args(&self) -> Vec<String> {
+        vec![
+            "--tls-cert".to_string(),
+            self.path("server.pem"),
+            "--tls-key".to_string(),
+            self.path("server.key"),
+        ]
+    }
+
+    fn connect(
+        &self,
+        url: &str,
+        with_client_cert: bool,
+    ) -> StreamOwned<ClientConnection, TcpStream> {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.ca.clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = if with_client_cert {
+            let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.client_key.clone()));
+            builder
+                .with_client_auth_cert(vec![self.client_cert.clone()], key)
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let conn =
+            ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap())
+                .unwrap();
+        let stream = TcpStream::connect(url).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        StreamOwned::new(conn, stream)
+    }
+}
+
+impl Drop for Certificates {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn args(args: &[String]) -> Vec<&str> {
+    args.iter().map(|a| a.as_str()).collect()
+}
+
+#[test]
+fn test_smoke_test_over_tls() {
+    let certs = Certificates::generate("smoke-test");
+    let server = common::ServerProcess::run_smoke_test_with_args(&args(&certs.server_args()));
+
+    let mut stream = certs.connect(&server.url(), false);
+    let payload: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    stream.write_all(&payload).unwrap();
+    stream.conn.send_close_notify();
+    stream.flush().unwrap();
+
+    let mut echo = vec![];
+    stream.read_to_end(&mut echo).unwrap();
+    assert_eq!(echo, payload);
+}
+
+#[test]
+fn test_prime_time_over_tls() {
+    let certs = Certificates::generate("prime-time");
+    let server = common::ServerProcess::run_prime_time_with_args(&args(&certs.server_args()));
+
+    let mut stream = certs.connect(&server.url(), false);
+    stream
+        .write_all(b"{\"method\":\"isPrime\",\"number\":7}\n")
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(&mut stream).read_line(&mut line).unwrap();
+    assert_eq!(line, "{\"method\":\"isPrime\",\"prime\":true}\n");
+}
+
+#[test]
+fn test_tls_silent_connections() {
+    let certs = Certificates::generate("silent");
+    let server = common::ServerProcess::run_smoke_test_with_args(&args(&certs.server_args()));
+
+    // More connections than there are workers, none of which start a
+    // handshake, mustn't hold up one that does. Were it stuck behind them,
+    // this would time out before the server gave up on them.
+    let _silent: Vec<_> = (0..10).map(|_| server.get_stream()).collect();
+    let mut stream = certs.connect(&server.url(), false);
+    stream.write_all(b"Hello world!").unwrap();
+    stream.conn.send_close_notify();
+    stream.flush().unwrap();
+
+    let mut echo = vec![];
+    stream.read_to_end(&mut echo).unwrap();
+    assert_eq!(echo, b"Hello world!");
+}
+
+#[test]
+fn test_tls_rejects_plaintext() {
+    let certs = Certificates::generate("plaintext");
+    let server = common::ServerProcess::run_smoke_test_with_args(&args(&certs.server_args()));
+
+    let response = server.send_request(b"Hello world!\n");
+    assert_ne!(response, b"Hello world!\n");
+}
+
+#[test]
+fn test_tls_client_certificates() {
+    let certs = Certificates::generate("client-ca");
+    let mut server_args = certs.server_args();
+    server_args.extend(["--tls-client-ca".to_string(), certs.path("ca.pem")]);
+    let server = common::ServerProcess::run_smoke_test_with_args(&args(&server_args));
+
+    // Without a certificate, the server hangs up as soon as it sees that.
+    let mut stream = certs.connect(&server.url(), false);
+    let mut echo = vec![];
+    let result = stream
+        .write_all(b"Hello")
+        .and_then(|_| stream.read_to_end(&mut echo));
+    assert!(result.is_err() || echo.is_empty(), "{:?}", echo);
+
+    let mut stream = certs.connect(&server.url(), true);
+    stream.write_all(b"Hello").unwrap();
+    stream.conn.send_close_notify();
+    stream.flush().unwrap();
+    let mut echo = vec![];
+    stream.read_to_end(&mut echo).unwrap();
+    assert_eq!(echo, b"Hello");
+}
+
+#[test]
+fn test_tls_missing_certificate() {
+    let output = common::run_command(
+        &[
+            "smoke-test",
+            "server",
+            "--tls-cert",
+            "/nonexistent/cert.pem",
+            "--tls-key",
+            "/nonexistent/key.pem",
+        ],
+        b"",
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Couldn't read /nonexistent/cert.pem"),
+        "{}",
+        stderr
+    );
+}